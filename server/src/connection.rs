@@ -1,8 +1,18 @@
-use std::sync::{atomic::AtomicBool, Arc};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
 
 use super::*;
 use futures_util::{SinkExt, StreamExt};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+};
+use tokio_rustls::{
+    rustls::{self, pki_types::ServerName},
+    TlsAcceptor, TlsConnector,
+};
 use tokio_tungstenite::{
     tungstenite::{
         protocol::{frame::coding::CloseCode, CloseFrame},
@@ -14,6 +24,16 @@ use tokio_tungstenite::{
 pub trait Packet: Sized + Send {
     fn serialize(self) -> Vec<u8>;
     fn parse(buf: Vec<u8>) -> anyhow::Result<Self>;
+
+    /// Can be dropped when a newer replaceable packet is waiting to be sent.
+    fn replaceable(&self) -> bool {
+        false
+    }
+
+    /// Login asks for outbound packets to be framed and compressed.
+    fn compression(&self) -> bool {
+        false
+    }
 }
 impl Packet for Vec<u8> {
     fn serialize(self) -> Vec<u8> {
@@ -25,11 +45,88 @@ impl Packet for Vec<u8> {
     }
 }
 
+/// Used for every listener and the instance to database link when set.
+pub struct Tls {
+    acceptor: TlsAcceptor,
+    connector: TlsConnector,
+    /// Name the database's certificate is verified against.
+    database_server_name: ServerName<'static>,
+}
+impl Tls {
+    /// `cert_path` is a pem certificate chain and `key_path` its pem private key.
+    ///
+    /// The database is verified with the certificates in `ca_path`.
+    /// Without it, `cert_path` is trusted directly which requires a non-CA certificate.
+    pub fn load(
+        cert_path: &str,
+        key_path: &str,
+        ca_path: Option<&str>,
+        database_server_name: ServerName<'static>,
+    ) -> anyhow::Result<Self> {
+        let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(
+            std::fs::File::open(cert_path).context("Failed to open tls certificate")?,
+        ))
+        .collect::<Result<Vec<_>, _>>()?;
+        let key = rustls_pemfile::private_key(&mut std::io::BufReader::new(
+            std::fs::File::open(key_path).context("Failed to open tls key")?,
+        ))?
+        .context("No private key found")?;
+
+        let mut roots = rustls::RootCertStore::empty();
+        if let Some(ca_path) = ca_path {
+            for cert in rustls_pemfile::certs(&mut std::io::BufReader::new(
+                std::fs::File::open(ca_path).context("Failed to open tls ca")?,
+            )) {
+                roots.add(cert?)?;
+            }
+        } else {
+            for cert in certs.iter() {
+                roots.add(cert.clone())?;
+            }
+        }
+
+        let server_config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        let client_config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(server_config)),
+            connector: TlsConnector::from(Arc::new(client_config)),
+            database_server_name,
+        })
+    }
+}
+
+/// Deflated packets are prefixed with this.
+const FRAME_DEFLATE: u8 = 1;
+/// Packets sent as is are prefixed with this.
+const FRAME_RAW: u8 = 0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionConfig {
+    /// Connection is closed once it has more than this waiting to be sent.
+    pub max_queued_bytes: usize,
+    /// Outbound packets of at least this size are deflated.
+    /// When some and the login opts in, every outbound packet is prefixed with a frame byte.
+    /// Inbound packets are never compressed.
+    pub compression_threshold: Option<usize>,
+}
+impl ConnectionConfig {
+    /// Between database and instances.
+    pub const INTERNAL: Self = Self {
+        max_queued_bytes: usize::MAX,
+        compression_threshold: None,
+    };
+}
+
 pub struct ConnectionListener<T: Packet> {
     new_connection_receiver: Receiver<(Connection, T)>,
 }
 impl<T: Packet + 'static> ConnectionListener<T> {
-    pub fn bind(addr: SocketAddr) -> anyhow::Result<Self> {
+    pub fn bind(addr: SocketAddr, config: ConnectionConfig) -> anyhow::Result<Self> {
         let listener = tokio().block_on(async move { TcpListener::bind(addr).await })?;
 
         let (new_connection_sender, new_connection_receiver) = unbounded();
@@ -50,7 +147,7 @@ impl<T: Packet + 'static> ConnectionListener<T> {
                 tokio::spawn(async move {
                     log::debug!("New connection attempt from {}", addr);
 
-                    match Connection::accept_stream::<T>(stream, addr).await {
+                    match Connection::accept_stream::<T>(stream, addr, config).await {
                         Ok((connection, login, stream, inbound_sender)) => {
                             if new_connection_sender.send((connection, login)).is_err() {
                                 closed.store(true, std::sync::atomic::Ordering::Relaxed);
@@ -78,17 +175,92 @@ impl<T: Packet + 'static> ConnectionListener<T> {
     }
 }
 
+/// Totals over every connection.
+pub static OUTBOUND_COUNTERS: OutboundCounters = OutboundCounters {
+    sent_bytes: AtomicU64::new(0),
+    dropped_packets: AtomicU64::new(0),
+    evicted_connections: AtomicU64::new(0),
+    compression_saved_bytes: AtomicU64::new(0),
+};
+
+pub struct OutboundCounters {
+    pub sent_bytes: AtomicU64,
+    /// Replaceable packets which were never sent.
+    pub dropped_packets: AtomicU64,
+    /// Connections closed for being too far behind.
+    pub evicted_connections: AtomicU64,
+    /// Bytes saved by compression.
+    pub compression_saved_bytes: AtomicU64,
+}
+
+enum Outbound {
+    Packet { buf: Vec<u8>, replaceable: bool },
+    Flush,
+    Close(&'static str),
+}
+
+/// Shared between the queuing side and the sink task.
+struct OutboundQueue {
+    max_queued_bytes: usize,
+    queued_bytes: AtomicUsize,
+    /// Replaceable packets in the queue.
+    queued_replaceable: AtomicUsize,
+    /// Set once the connection is too far behind.
+    evicted: AtomicBool,
+    sent_bytes: AtomicU64,
+    /// Set from the login, before anything is queued.
+    compression: AtomicBool,
+}
+
 #[derive(Clone)]
 pub struct ConnectionOutbound {
-    outbound_sender: tokio::sync::mpsc::UnboundedSender<Result<Option<Vec<u8>>, &'static str>>,
+    outbound_sender: tokio::sync::mpsc::UnboundedSender<Outbound>,
+    queue: Arc<OutboundQueue>,
 }
 impl ConnectionOutbound {
     pub fn queue(&self, packet: impl Packet) {
-        let _ = self.outbound_sender.send(Ok(Some(packet.serialize())));
+        if self.queue.evicted.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let replaceable = packet.replaceable();
+        let buf = packet.serialize();
+
+        let queued_bytes = self
+            .queue
+            .queued_bytes
+            .fetch_add(buf.len(), Ordering::Relaxed);
+        if queued_bytes + buf.len() > self.queue.max_queued_bytes {
+            self.queue
+                .queued_bytes
+                .fetch_sub(buf.len(), Ordering::Relaxed);
+            if !self.queue.evicted.swap(true, Ordering::Relaxed) {
+                OUTBOUND_COUNTERS
+                    .evicted_connections
+                    .fetch_add(1, Ordering::Relaxed);
+                // Sink will skip everything up to this.
+                let _ = self.outbound_sender.send(Outbound::Close("Too far behind"));
+            }
+            return;
+        }
+
+        if replaceable {
+            self.queue
+                .queued_replaceable
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        let _ = self
+            .outbound_sender
+            .send(Outbound::Packet { buf, replaceable });
     }
 
     pub fn flush(&self) {
-        let _ = self.outbound_sender.send(Ok(None));
+        let _ = self.outbound_sender.send(Outbound::Flush);
+    }
+
+    /// Total sent over this connection.
+    pub fn sent_bytes(&self) -> u64 {
+        self.queue.sent_bytes.load(Ordering::Relaxed)
     }
 }
 
@@ -129,11 +301,25 @@ impl Connection {
 
     async fn accept_client(server_addr: SocketAddr) -> anyhow::Result<Self> {
         let stream = TcpStream::connect(server_addr).await?;
-        let ws = tokio_tungstenite::client_async(format!("ws://{}", server_addr), stream)
+        let _ = stream.set_nodelay(true);
+
+        let (stream, scheme): (Stream, _) = if let Some(tls) = &data().tls {
+            let stream = tls
+                .connector
+                .connect(tls.database_server_name.clone(), stream)
+                .await?;
+            (Box::new(stream), "wss")
+        } else {
+            (Box::new(stream), "ws")
+        };
+
+        let ws = tokio_tungstenite::client_async(format!("{}://{}", scheme, server_addr), stream)
             .await?
             .0;
 
-        let (connection, stream, inbound_sender) = Connection::accept(ws, server_addr).await?;
+        // Only used to connect to the database.
+        let (connection, stream, inbound_sender) =
+            Connection::accept(ws, server_addr, ConnectionConfig::INTERNAL).await?;
         let addr = connection.peer_addr;
         tokio::spawn(Connection::inbound_loop(stream, inbound_sender, addr));
 
@@ -143,41 +329,102 @@ impl Connection {
     async fn accept_stream<T: Packet>(
         stream: TcpStream,
         addr: SocketAddr,
+        config: ConnectionConfig,
     ) -> anyhow::Result<(Self, T, WsStream, Sender<Vec<u8>>)> {
+        let _ = stream.set_nodelay(true);
+
+        let stream: Stream = if let Some(tls) = &data().tls {
+            Box::new(tls.acceptor.accept(stream).await?)
+        } else {
+            Box::new(stream)
+        };
+
         let ws = tokio_tungstenite::accept_async(stream).await?;
-        let (connection, mut stream, inbound_sender) = Connection::accept(ws, addr).await?;
+        let (connection, mut stream, inbound_sender) = Connection::accept(ws, addr, config).await?;
 
         let login = T::parse(read_vec(&mut stream).await?)?;
+        connection
+            .outbound
+            .queue
+            .compression
+            .store(login.compression(), Ordering::Relaxed);
 
         Ok((connection, login, stream, inbound_sender))
     }
 
     async fn accept(
-        mut ws: WebSocketStream<TcpStream>,
+        ws: WebSocketStream<Stream>,
         addr: SocketAddr,
+        config: ConnectionConfig,
     ) -> anyhow::Result<(Self, WsStream, Sender<Vec<u8>>)> {
-        let _ = ws.get_mut().set_nodelay(true);
         let (mut sink, stream) = ws.split();
 
+        let queue = Arc::new(OutboundQueue {
+            max_queued_bytes: config.max_queued_bytes,
+            queued_bytes: Default::default(),
+            queued_replaceable: Default::default(),
+            evicted: Default::default(),
+            sent_bytes: Default::default(),
+            compression: Default::default(),
+        });
+
         // Outbound loop
         let (outbound_sender, mut outbound_receiver) =
-            tokio::sync::mpsc::unbounded_channel::<Result<Option<Vec<u8>>, &'static str>>();
+            tokio::sync::mpsc::unbounded_channel::<Outbound>();
+        let sink_queue = queue.clone();
         tokio::spawn(async move {
-            while let Some(buf) = outbound_receiver.recv().await {
-                match buf {
-                    Ok(Some(buf)) => {
+            while let Some(outbound) = outbound_receiver.recv().await {
+                match outbound {
+                    Outbound::Packet { buf, replaceable } => {
+                        sink_queue
+                            .queued_bytes
+                            .fetch_sub(buf.len(), Ordering::Relaxed);
+
+                        if sink_queue.evicted.load(Ordering::Relaxed) {
+                            continue;
+                        }
+
+                        // A newer one is queued.
+                        if replaceable
+                            && sink_queue
+                                .queued_replaceable
+                                .fetch_sub(1, Ordering::Relaxed)
+                                > 1
+                        {
+                            OUTBOUND_COUNTERS
+                                .dropped_packets
+                                .fetch_add(1, Ordering::Relaxed);
+                            continue;
+                        }
+
+                        let buf = match config.compression_threshold {
+                            Some(threshold) if sink_queue.compression.load(Ordering::Relaxed) => {
+                                frame(buf, threshold)
+                            }
+                            _ => buf,
+                        };
+
+                        let len = buf.len() as u64;
                         if let Err(err) = sink.feed(Message::Binary(buf)).await {
                             log::debug!("Failed to feed packet to {}: {}", addr, err);
                             break;
                         }
+                        OUTBOUND_COUNTERS
+                            .sent_bytes
+                            .fetch_add(len, Ordering::Relaxed);
+                        sink_queue.sent_bytes.fetch_add(len, Ordering::Relaxed);
                     }
-                    Ok(None) => {
+                    Outbound::Flush => {
+                        if sink_queue.evicted.load(Ordering::Relaxed) {
+                            continue;
+                        }
+
                         if let Err(err) = sink.flush().await {
                             log::debug!("Failed to flush packets to {}: {}", addr, err);
                             break;
                         }
                     }
-                    Err(close_reason) => {
+                    Outbound::Close(close_reason) => {
                         let _ = sink
                             .send(Message::Close(Some(CloseFrame {
                                 code: CloseCode::Normal,
@@ -198,7 +445,10 @@ impl Connection {
             Self {
                 peer_addr: addr,
                 inbound: ConnectionInbound { inbound_receiver },
-                outbound: ConnectionOutbound { outbound_sender },
+                outbound: ConnectionOutbound {
+                    outbound_sender,
+                    queue,
+                },
             },
             stream,
             inbound_sender,
@@ -223,8 +473,10 @@ impl Connection {
     }
 
     pub fn close(&self, reason: &'static str) {
-        self.outbound.outbound_sender.send(Err(reason)).ok();
-        self.flush();
+        self.outbound
+            .outbound_sender
+            .send(Outbound::Close(reason))
+            .ok();
     }
 
     pub fn recv<T: Packet>(&mut self) -> Result<T, TryRecvError> {
@@ -232,7 +484,39 @@ impl Connection {
     }
 }
 
-type WsStream = futures_util::stream::SplitStream<WebSocketStream<TcpStream>>;
+/// Plain tcp or tls.
+trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+type Stream = Box<dyn AsyncStream>;
+
+type WsStream = futures_util::stream::SplitStream<WebSocketStream<Stream>>;
+
+/// Prefix with the frame byte, deflating if large enough and worth it.
+fn frame(buf: Vec<u8>, compression_threshold: usize) -> Vec<u8> {
+    if buf.len() >= compression_threshold {
+        let mut encoder = flate2::write::DeflateEncoder::new(
+            Vec::with_capacity(buf.len() / 2 + 1),
+            flate2::Compression::fast(),
+        );
+        let compressed = std::io::Write::write_all(&mut encoder, &buf)
+            .and_then(|_| encoder.finish())
+            .ok()
+            .filter(|compressed| compressed.len() < buf.len());
+
+        if let Some(mut compressed) = compressed {
+            OUTBOUND_COUNTERS
+                .compression_saved_bytes
+                .fetch_add((buf.len() - compressed.len()) as u64, Ordering::Relaxed);
+            compressed.insert(0, FRAME_DEFLATE);
+            return compressed;
+        }
+    }
+
+    let mut framed = Vec::with_capacity(buf.len() + 1);
+    framed.push(FRAME_RAW);
+    framed.extend_from_slice(&buf);
+    framed
+}
 
 async fn read_vec(stream: &mut WsStream) -> anyhow::Result<Vec<u8>> {
     while let Some(msg) = stream.next().await {
@@ -244,3 +528,63 @@ async fn read_vec(stream: &mut WsStream) -> anyhow::Result<Vec<u8>> {
     }
     anyhow::bail!("Connection closed");
 }
+
+// ####################################################################################
+// ############## TEST ################################################################
+// ####################################################################################
+
+/// Packets queued to a [ConnectionOutbound] which is not connected to anything.
+#[cfg(test)]
+pub struct TestOutbound(tokio::sync::mpsc::UnboundedReceiver<Outbound>);
+#[cfg(test)]
+impl TestOutbound {
+    pub fn new() -> (ConnectionOutbound, Self) {
+        let (outbound_sender, outbound_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let outbound = ConnectionOutbound {
+            outbound_sender,
+            queue: Arc::new(OutboundQueue {
+                max_queued_bytes: usize::MAX,
+                queued_bytes: Default::default(),
+                queued_replaceable: Default::default(),
+                evicted: Default::default(),
+                sent_bytes: Default::default(),
+                compression: Default::default(),
+            }),
+        };
+        (outbound, Self(outbound_receiver))
+    }
+
+    /// Packets queued since last call. Those which are not a `T` are skipped.
+    pub fn packets<T: Packet>(&mut self) -> Vec<T> {
+        std::iter::from_fn(|| self.0.try_recv().ok())
+            .filter_map(|outbound| match outbound {
+                Outbound::Packet { buf, .. } => T::parse(buf).ok(),
+                Outbound::Flush | Outbound::Close(_) => None,
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_frame() {
+    use std::io::Read;
+
+    let small = vec![1, 2, 3];
+    assert_eq!(
+        frame(small.clone(), 64),
+        [&[FRAME_RAW][..], &small].concat()
+    );
+
+    let large = (0..1000u32)
+        .flat_map(|i| (i % 10).to_le_bytes())
+        .collect::<Vec<_>>();
+    let framed = frame(large.clone(), 64);
+    assert_eq!(framed[0], FRAME_DEFLATE);
+    assert!(framed.len() < large.len());
+
+    let mut inflated = Vec::new();
+    flate2::read::DeflateDecoder::new(&framed[1..])
+        .read_to_end(&mut inflated)
+        .unwrap();
+    assert_eq!(inflated, large);
+}
@@ -1,6 +1,8 @@
 use super::*;
 use simulation::entity::{EntityData, EntityDataJson};
+use simulation::turret::{WeaponData, WeaponDataJson};
 use std::{fs::File, io::BufReader};
+use tokio_rustls::rustls::pki_types::ServerName;
 
 const DATA_PATH: &str = "eos/client/tool/server_data.json";
 const CONFIG_PATH: &str = "config.json";
@@ -16,9 +18,14 @@ pub fn data() -> &'static Data {
 pub struct Data {
     pub database_addr: SocketAddr,
     pub database_key: Vec<u8>,
+    /// Plaintext when none.
+    pub tls: Option<Tls>,
+    /// Prometheus exporter is disabled when none.
+    pub metrics_addr: Option<SocketAddr>,
     pub instances: AHashMap<InstanceId, InstanceData>,
     pub simulations: AHashMap<SimulationId, SimulationData>,
     pub entities: Vec<EntityData>,
+    pub weapons: Vec<WeaponData>,
 
     first_ship: usize,
 }
@@ -93,22 +100,58 @@ fn parse_json(config: ConfigJson, json: DataJson) -> Data {
         }
     });
 
+    let weapons = json
+        .weapons
+        .into_iter()
+        .zip(0u32..)
+        .map(|(weapon_json, id)| weapon_json.parse(id))
+        .collect::<Vec<_>>();
+
     let entities = json
         .entities
         .into_iter()
         .zip(0u32..)
-        .map(|(entity_json, id)| entity_json.parse(id))
+        .map(|(entity_json, id)| {
+            entity_json
+                .parse(id, &weapons)
+                .with_context(|| format!("Invalid entity data {}", id))
+                .unwrap()
+        })
         .collect::<Vec<_>>();
 
     let first_ship = json.first_ship;
     assert!(first_ship < entities.len());
 
+    let database_addr: SocketAddr = config.database_addr.parse().unwrap();
+
+    let tls = config.tls.map(|tls| {
+        let database_server_name = tls
+            .database_server_name
+            .map(|name| ServerName::try_from(name).unwrap())
+            .unwrap_or_else(|| ServerName::IpAddress(database_addr.ip().into()));
+        Tls::load(
+            &tls.cert_path,
+            &tls.key_path,
+            tls.ca_path.as_deref(),
+            database_server_name,
+        )
+        .unwrap()
+    });
+    if tls.is_none() {
+        log::warn!("Tls not configured. Connections are not encrypted");
+    }
+
     Data {
-        database_addr: config.database_addr.parse().unwrap(),
+        database_addr,
         database_key: config.database_key.into_bytes(),
+        tls,
+        metrics_addr: config
+            .metrics_addr
+            .map(|addr| addr.parse().context("Invalid metrics address").unwrap()),
         instances,
         simulations,
         entities,
+        weapons,
         first_ship,
     }
 }
@@ -122,6 +165,24 @@ fn parse_json(config: ConfigJson, json: DataJson) -> Data {
 struct ConfigJson {
     database_addr: String,
     database_key: String,
+    /// Plaintext when none. Only for local testing.
+    #[serde(default)]
+    tls: Option<TlsConfigJson>,
+    /// Should be a local address like `127.0.0.1:9100`.
+    #[serde(default)]
+    metrics_addr: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TlsConfigJson {
+    cert_path: String,
+    key_path: String,
+    /// Used to verify the database's certificate instead of `cert_path`.
+    #[serde(default)]
+    ca_path: Option<String>,
+    /// Defaults to the database's ip.
+    #[serde(default)]
+    database_server_name: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -129,6 +190,8 @@ struct DataJson {
     instances: AHashMap<InstanceId, String>,
     simulations: AHashMap<SimulationId, SimulationDataJson>,
     entities: Vec<EntityDataJson>,
+    #[serde(default)]
+    weapons: Vec<WeaponDataJson>,
     first_ship: usize,
 }
 
@@ -145,6 +208,8 @@ fn config_test() -> ConfigJson {
     ConfigJson {
         database_addr: "[::1]:0".to_string(),
         database_key: "key".to_string(),
+        tls: None,
+        metrics_addr: None,
     }
 }
 
@@ -173,6 +238,7 @@ fn json_test() -> DataJson {
             )
         })),
         entities: vec![Default::default()],
+        weapons: vec![Default::default()],
         first_ship: 0,
     }
 }
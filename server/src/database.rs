@@ -1,7 +1,12 @@
 use super::*;
+use argon2::password_hash::{
+    rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+};
+use argon2::Argon2;
 use chrono::{DateTime, FixedOffset, Utc};
 use instance::ClientLoginType;
 use rayon::prelude::*;
+use session::SessionToken;
 use simulation::{entity::EntitySave, SimulationSave};
 use std::{
     fs::File,
@@ -12,7 +17,10 @@ use std::{
 /// Save every 4 hours.
 const SAVE_INTERVAL: Duration = Duration::from_secs(4 * 60 * 60);
 const KEEP_DATABASE_FILES_AMOUNT: usize = 12;
+/// Password logins and registrations past this are refused until some complete.
+const MAX_PENDING_PASSWORDS: usize = 64;
 
+/// Logged to the mutations file by variant index. New variants go at the end.
 #[derive(Serialize, Deserialize)]
 pub enum DatabaseRequest {
     SaveAndRestart {
@@ -39,6 +47,33 @@ pub enum DatabaseRequest {
         save: EntitySave,
     },
     Query(DatabaseQuery),
+    /// Same as [DatabaseRequest::SaveShip] for every ship in a simulation.
+    SaveShips {
+        simulation_id: SimulationId,
+        ships: Vec<(ShipId, EntitySave)>,
+    },
+    /// Logged in place of a successful [ClientLoginType::RegisterUsernamePassword]
+    /// so that plaintext passwords never reach the mutations file.
+    AddClient {
+        username: String,
+        password_hash: String,
+    },
+    /// Invalidate every session token issued to this client so far.
+    RevokeSessionTokens {
+        client_id: ClientId,
+    },
+    /// Sent by simulations when a client enters.
+    /// Older sessions of the same client are kicked.
+    ClientConnected {
+        client_id: ClientId,
+        session_id: u64,
+        simulation_id: SimulationId,
+    },
+    /// Ignored if the session was replaced.
+    ClientDisconnected {
+        client_id: ClientId,
+        session_id: u64,
+    },
 }
 impl Packet for DatabaseRequest {
     fn serialize(self) -> Vec<u8> {
@@ -59,6 +94,11 @@ pub enum DatabaseQuery {
         client_id: ClientId,
         from: SimulationId,
     },
+    /// Will respond with [DatabaseSimulationResponse::OnlineCount].
+    OnlineCount {
+        client_id: ClientId,
+        from: SimulationId,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
@@ -66,6 +106,19 @@ pub enum DatabaseResponse {
     ClientAuthResult {
         client_id: Option<ClientId>,
         response_token: u64,
+        /// Encoded [SessionToken] for the authenticated client.
+        session_token: Option<Vec<u8>>,
+    },
+    /// Client logged in from elsewhere.
+    /// Should be disconnected if it is still this session.
+    KickClient {
+        client_id: ClientId,
+        session_id: u64,
+    },
+    /// Session tokens with a lower generation are revoked for this client.
+    SessionTokensRevoked {
+        client_id: ClientId,
+        generation: u32,
     },
     HandleSimulation {
         simulation_id: SimulationId,
@@ -99,6 +152,12 @@ pub enum DatabaseSimulationResponse {
         ship_id: ShipId,
         save: EntitySave,
     },
+    OnlineCount {
+        client_id: ClientId,
+        total: u32,
+        /// Online clients in the simulation which made the query.
+        simulation: u32,
+    },
 }
 
 #[derive(Serialize)]
@@ -133,6 +192,14 @@ struct Database {
     instances: IndexMap<InstanceId, Instance, RandomState>,
     #[serde(skip)]
     queries: Vec<(DatabaseQuery, InstanceId)>,
+    /// Logged-in clients.
+    #[serde(skip)]
+    presences: AHashMap<ClientId, Presence>,
+    /// Argon2 work is done on the rayon pool. Results are handled on the next step.
+    #[serde(skip)]
+    password_results: (Sender<PasswordResult>, Receiver<PasswordResult>),
+    #[serde(skip)]
+    pending_passwords: usize,
 
     simulations: AHashMap<SimulationId, Simulation>,
     ships: AHashMap<ShipId, Ship>,
@@ -144,6 +211,25 @@ struct Database {
 struct Instance {
     connection: Connection,
 }
+enum PasswordResult {
+    Login {
+        client_id: Option<ClientId>,
+        response_token: u64,
+        from: Option<InstanceId>,
+    },
+    Register {
+        username: String,
+        password_hash: anyhow::Result<String>,
+        response_token: u64,
+        from: Option<InstanceId>,
+    },
+}
+struct Presence {
+    instance_id: InstanceId,
+    simulation_id: SimulationId,
+    session_id: u64,
+    connected_since: DateTime<Utc>,
+}
 #[derive(Serialize, Deserialize, Default)]
 #[serde(default)]
 struct Simulation {
@@ -161,9 +247,14 @@ struct Ship {
 #[derive(Serialize, Deserialize, Default)]
 #[serde(default)]
 struct Client {
-    password: Option<String>,
+    /// Argon2 PHC string.
+    /// Older json saves have the plaintext password which is hashed on load.
+    #[serde(alias = "password")]
+    password_hash: Option<String>,
     #[serde(skip)]
     ships: AHashSet<ShipId>,
+    /// Incremented to revoke all previously issued session tokens.
+    token_generation: u32,
 }
 
 // ####################################################################################
@@ -178,9 +269,16 @@ impl Default for Database {
             save_request: 0,
             restart_request: None,
             mut_requests_writer: None,
-            connection_listener: ConnectionListener::bind(data().database_addr).unwrap(),
+            connection_listener: ConnectionListener::bind(
+                data().database_addr,
+                ConnectionConfig::INTERNAL,
+            )
+            .unwrap(),
             instances: Default::default(),
             queries: Default::default(),
+            presences: Default::default(),
+            password_results: unbounded(),
+            pending_passwords: 0,
             simulations: Default::default(),
             ships: Default::default(),
             next_client_id: Default::default(),
@@ -198,7 +296,7 @@ impl Database {
     /// Checks that all data is valid.
     fn prepare(&mut self) {
         // Check that all simulations exist.
-        for (simulation_id, simulation_data) in data().simulations.iter() {
+        for simulation_id in data().simulations.keys() {
             self.simulations
                 .entry(*simulation_id)
                 .or_insert_with(|| Simulation {
@@ -220,6 +318,41 @@ impl Database {
             }
         }
 
+        // Hash plaintext passwords from older saves.
+        let num_migrated = self
+            .clients
+            .par_iter_mut()
+            .filter_map(|(client_id, client)| {
+                let password = client.password_hash.as_deref()?;
+                if PasswordHash::new(password).is_ok() {
+                    return None;
+                }
+
+                match hash_password(password) {
+                    Ok(password_hash) => client.password_hash = Some(password_hash),
+                    Err(err) => {
+                        log::error!(
+                            "Failed to hash {:?}'s password: {}. Removing password",
+                            client_id,
+                            err
+                        );
+                        client.password_hash = None;
+                    }
+                }
+
+                Some(())
+            })
+            .count();
+        if num_migrated > 0 {
+            log::warn!("Hashed {} plaintext passwords", num_migrated);
+        }
+
+        for simulation in self.simulations.values_mut() {
+            for wreck in simulation.simulation_save.wrecks.iter_mut() {
+                wreck.verify();
+            }
+        }
+
         self.ships.retain(|&ship_id, ship| {
             if let Some(simulation) = self.simulations.get_mut(&ship_id.origin_simulation_id()) {
                 simulation.last_ship_id = simulation.last_ship_id.max(ship_id);
@@ -256,15 +389,53 @@ impl Database {
     }
 }
 
+// ####################################################################################
+// ############## PASSWORD ############################################################
+// ####################################################################################
+
+/// Salted argon2 hash as a PHC string.
+fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| anyhow::anyhow!("{}", err))
+}
+
+/// Constant-time comparison.
+/// Still hash the password when there is no hash to compare against.
+fn verify_password(password: &str, password_hash: Option<&str>) -> bool {
+    static DUMMY_HASH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+    let (password_hash, dummy) = match password_hash {
+        Some(password_hash) => (password_hash, false),
+        None => (
+            DUMMY_HASH
+                .get_or_init(|| hash_password("dummy").unwrap())
+                .as_str(),
+            true,
+        ),
+    };
+
+    let Ok(parsed_hash) = PasswordHash::new(password_hash) else {
+        return false;
+    };
+
+    Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_ok()
+        && !dummy
+}
+
 // ####################################################################################
 // ############## LOAD ################################################################
 // ####################################################################################
 
 /// database_2021-09-18T18:00:00+00:00.bin
-const DATABASE_PREFIX: &'static str = "database_";
-const DATABASE_JSON_SUFFIX: &'static str = ".json";
-const DATABASE_BIN_SUFFIX: &'static str = ".bin";
-const MUT_REQUESTS_FILE: &'static str = "mutations.bin";
+const DATABASE_PREFIX: &str = "database_";
+const DATABASE_JSON_SUFFIX: &str = ".json";
+const DATABASE_BIN_SUFFIX: &str = ".bin";
+const MUT_REQUESTS_FILE: &str = "mutations.bin";
 
 struct DatabaseSavePath {
     path: PathBuf,
@@ -309,6 +480,11 @@ fn database_save_files() -> anyhow::Result<Vec<DatabaseSavePath>> {
     Ok(files)
 }
 
+/// Bin saves and the mutations file are not self-describing so they only load with the same
+/// `Database` and `DatabaseRequest` layout they were written with.
+/// Only json saves load across layout changes (added fields, plaintext password migration).
+/// Before upgrading, convert with `SaveAndRestart { save_json: true }` on the old build:
+/// the newest save is then json and the mutations file is empty.
 fn load_database() -> anyhow::Result<Database> {
     // Load newest database.
     let mut db: Database = if let Some(save) = database_save_files()?.pop() {
@@ -319,7 +495,9 @@ fn load_database() -> anyhow::Result<Database> {
             serde_json::from_reader(&mut reader)?
         } else {
             let mut buf = vec![0; 4096];
-            postcard::from_io((&mut reader, &mut buf)).map(|(db, _)| db)?
+            postcard::from_io((&mut reader, &mut buf))
+                .map(|(db, _)| db)
+                .context("bin save from an older layout? convert it to json first")?
         }
     } else {
         log::warn!("No database file found. Creating default one");
@@ -342,7 +520,8 @@ fn load_database() -> anyhow::Result<Database> {
                 let len = u32::from_le_bytes(buf);
                 request_buf.resize(len as usize, 0);
                 reader.read_exact(&mut request_buf)?;
-                db.handle_request(&request_buf, None)?;
+                db.handle_request(&request_buf, None)
+                    .context("mutation from an older layout? convert the save to json first")?;
             }
 
             log::info!("Previous database mutations applied");
@@ -416,16 +595,32 @@ impl Database {
 pub fn _start() {
     let mut db = load_database().unwrap();
 
-    let mut interval = interval::Interval::new(50, 50);
+    let mut interval = interval::Interval::new("database", 50, 50);
     loop {
         interval.step();
+
+        let start = Instant::now();
         if db.step() {
             break;
         }
+        db.update_metrics(start);
     }
 }
 
 impl Database {
+    fn update_metrics(&self, step_start: Instant) {
+        let metrics = metrics::metrics();
+        metrics.database_clients.set(self.clients.len() as i64);
+        metrics
+            .database_online_clients
+            .set(self.presences.len() as i64);
+        metrics.database_ships.set(self.ships.len() as i64);
+        metrics.database_instances.set(self.instances.len() as i64);
+        metrics
+            .database_step_seconds
+            .observe(step_start.elapsed().as_secs_f64());
+    }
+
     /// Return if disconnected.
     fn step(&mut self) -> bool {
         // Get new instances.
@@ -464,6 +659,16 @@ impl Database {
                 }
             }
 
+            // Send revoked session tokens.
+            for (&client_id, client) in self.clients.iter() {
+                if client.token_generation > 0 {
+                    connection.queue(DatabaseResponse::SessionTokensRevoked {
+                        client_id,
+                        generation: client.token_generation,
+                    });
+                }
+            }
+
             connection.flush();
 
             self.instances
@@ -480,11 +685,19 @@ impl Database {
                 }
                 Err(TryRecvError::Empty) => i += 1,
                 Err(TryRecvError::Disconnected) => {
-                    self.instances.swap_remove_index(i);
+                    let (instance_id, _) = self.instances.swap_remove_index(i).unwrap();
+                    self.presences
+                        .retain(|_, presence| presence.instance_id != instance_id);
                 }
             }
         }
 
+        while let Ok(result) = self.password_results.1.try_recv() {
+            if let Err(err) = self.handle_password_result(result) {
+                log::error!("Failed to handle password result: {}", err);
+            }
+        }
+
         // Handle queries in parallel.
         self.queries.par_iter().for_each(|(query, from)| {
             if let Err(err) = self.handle_query(query, *from) {
@@ -547,22 +760,57 @@ impl Database {
                 login,
                 response_token,
             } => {
-                let client_id = match login {
+                let from = from.map(|from| *self.instances.get_index(from).unwrap().0);
+
+                let password = matches!(
+                    login,
+                    ClientLoginType::LoginUsernamePassword { .. }
+                        | ClientLoginType::RegisterUsernamePassword { .. }
+                );
+                if password && self.pending_passwords >= MAX_PENDING_PASSWORDS {
+                    log::warn!("Too many pending password requests. Refusing login");
+                    self.send_auth_result(None, response_token, from);
+                    return Ok(());
+                }
+
+                match login {
                     ClientLoginType::LoginUsernamePassword { username, password } => {
-                        if let Some(client_id) = self.username.get(&username) {
-                            let client = self
-                                .clients
-                                .get_mut(client_id)
-                                .context("Client not found, but username exist")?;
-
-                            if client.password.as_deref() == Some(password.as_str()) {
-                                Some(*client_id)
-                            } else {
-                                None
-                            }
+                        let client_id = self.username.get(&username).copied();
+                        let password_hash = if let Some(client_id) = client_id {
+                            self.clients
+                                .get(&client_id)
+                                .context("Client not found, but username exist")?
+                                .password_hash
+                                .clone()
                         } else {
                             None
-                        }
+                        };
+
+                        // Always verify so that unknown usernames take as long as known ones.
+                        let sender = self.password_results.0.clone();
+                        self.pending_passwords += 1;
+                        rayon::spawn(move || {
+                            let client_id = client_id
+                                .filter(|_| verify_password(&password, password_hash.as_deref()));
+                            let _ = sender.send(PasswordResult::Login {
+                                client_id,
+                                response_token,
+                                from,
+                            });
+                        });
+                    }
+                    ClientLoginType::SessionToken { token } => {
+                        // Instances should verify tokens themselves, but it is cheap to do here too.
+                        let client_id = SessionToken::decode(&token, &data().database_key)
+                            .ok()
+                            .filter(|token| {
+                                self.clients.get(&token.client_id).is_some_and(|client| {
+                                    token.generation >= client.token_generation
+                                })
+                            })
+                            .map(|token| token.client_id);
+
+                        self.send_auth_result(client_id, response_token, from);
                     }
                     ClientLoginType::RegisterUsernamePassword { username, password } => {
                         if username.len() < 4
@@ -570,34 +818,24 @@ impl Database {
                             || password.len() < 4
                             || self.username.contains_key(&username)
                         {
-                            None
+                            self.send_auth_result(None, response_token, from);
                         } else {
-                            let client_id = self.next_client_id.next();
-                            self.username.insert(username, client_id);
-
-                            self.clients.insert(
-                                client_id,
-                                Client {
-                                    password: Some(password),
-                                    ..Default::default()
-                                },
-                            );
-
-                            Some(client_id)
+                            let sender = self.password_results.0.clone();
+                            self.pending_passwords += 1;
+                            rayon::spawn(move || {
+                                let _ = sender.send(PasswordResult::Register {
+                                    username,
+                                    password_hash: hash_password(&password),
+                                    response_token,
+                                    from,
+                                });
+                            });
                         }
                     }
-                };
-
-                if let Some(from) = from {
-                    self.instances[from]
-                        .connection
-                        .queue(DatabaseResponse::ClientAuthResult {
-                            client_id,
-                            response_token,
-                        });
                 }
 
-                true
+                // Registration is logged as AddClient.
+                false
             }
             DatabaseRequest::SaveSimulation {
                 simulation_id,
@@ -615,82 +853,17 @@ impl Database {
                 simulation_id,
                 save,
             } => {
-                let new_owner = save.owner;
-
-                let ship = Ship {
-                    simulation_id,
-                    save,
-                };
-
-                let mut remove_old_owner = None;
-                let mut add_new_owner = None;
-                let mut remove_new_simulation = None;
-                let mut add_new_simulation = None;
-
-                if let Some(old_ship) = self.ships.insert(ship_id, ship) {
-                    if old_ship.save.owner != new_owner {
-                        remove_old_owner = old_ship.save.owner;
-                        add_new_owner = new_owner;
-                    }
-
-                    if old_ship.simulation_id != simulation_id {
-                        remove_new_simulation = Some(old_ship.simulation_id);
-                        add_new_simulation = Some(simulation_id);
-                    }
-                } else {
-                    add_new_owner = new_owner;
-                    add_new_simulation = Some(simulation_id);
-
-                    if let Some(simulation) =
-                        self.simulations.get_mut(&ship_id.origin_simulation_id())
-                    {
-                        simulation.last_ship_id = simulation.last_ship_id.max(ship_id);
-                    }
-                }
-
-                if let Some(client_id) = remove_old_owner {
-                    self.clients
-                        .get_mut(&client_id)
-                        .context("Ship's previous owner not found")?
-                        .ships
-                        .remove(&ship_id);
-                }
-                if let Some(client_id) = add_new_owner {
-                    self.clients
-                        .get_mut(&client_id)
-                        .context("Ship's new owner not found")?
-                        .ships
-                        .insert(ship_id);
-                }
-
-                if let Some(simulation_id) = remove_new_simulation {
-                    self.simulations
-                        .get_mut(&simulation_id)
-                        .context("Ship's previous simulation not found")?
-                        .ships
-                        .remove(&ship_id);
-                }
-                if let Some(simulation_id) = add_new_simulation {
-                    self.simulations
-                        .get_mut(&simulation_id)
-                        .context("Ship's new simulation not found")?
-                        .ships
-                        .insert(ship_id);
+                self.save_ship(ship_id, simulation_id, save)?;
 
-                    // Notify new simulation
-                    if let Some(instance) = self
-                        .instances
-                        .get(&data().simulations[&simulation_id].instance_id)
-                    {
-                        instance
-                            .connection
-                            .queue(DatabaseResponse::DatabaseSimulationResponse {
-                                to: simulation_id,
-                                response: DatabaseSimulationResponse::ShipEntered {
-                                    ship_id,
-                                    save: self.ships[&ship_id].save.clone(),
-                                },
-                            });
+                true
+            }
+            DatabaseRequest::SaveShips {
+                simulation_id,
+                ships,
+            } => {
+                for (ship_id, save) in ships {
+                    if let Err(err) = self.save_ship(ship_id, simulation_id, save) {
+                        log::error!("Failed to save {:?}: {}", ship_id, err);
                     }
                 }
 
@@ -713,6 +886,14 @@ impl Database {
 
                 true
             }
+            DatabaseRequest::AddClient {
+                username,
+                password_hash,
+            } => {
+                self.add_client(username, password_hash);
+
+                true
+            }
             DatabaseRequest::CreateClientFirstShip { ship_id, save } => {
                 let client_id = save.owner.context("Ship has no owner")?;
                 let client = self
@@ -757,6 +938,53 @@ impl Database {
                     false
                 }
             }
+            DatabaseRequest::ClientConnected {
+                client_id,
+                session_id,
+                simulation_id,
+            } => {
+                if let Some(from) = from {
+                    let instance_id = *self.instances.get_index(from).unwrap().0;
+                    if let Some(kicked) =
+                        self.client_connected(client_id, session_id, instance_id, simulation_id)
+                    {
+                        if let Some(instance) = self.instances.get(&kicked.instance_id) {
+                            instance.connection.queue(DatabaseResponse::KickClient {
+                                client_id,
+                                session_id: kicked.session_id,
+                            });
+                        }
+                    }
+                }
+
+                false
+            }
+            DatabaseRequest::ClientDisconnected {
+                client_id,
+                session_id,
+            } => {
+                self.client_disconnected(client_id, session_id);
+
+                false
+            }
+            DatabaseRequest::RevokeSessionTokens { client_id } => {
+                let client = self
+                    .clients
+                    .get_mut(&client_id)
+                    .context("Client not found")?;
+                client.token_generation += 1;
+
+                for instance in self.instances.values() {
+                    instance
+                        .connection
+                        .queue(DatabaseResponse::SessionTokensRevoked {
+                            client_id,
+                            generation: client.token_generation,
+                        });
+                }
+
+                true
+            }
             DatabaseRequest::Query(query) => {
                 if let Some(from) = from {
                     self.queries
@@ -768,9 +996,227 @@ impl Database {
         };
 
         if save {
-            if let Some(writer) = &mut self.mut_requests_writer {
-                writer.write_all(&(request.len() as u32).to_le_bytes())?;
-                writer.write_all(request)?;
+            self.log_request(request)?;
+        }
+
+        Ok(())
+    }
+
+    /// Append a mutation request to be applied again if the database is restarted before saving.
+    fn log_request(&mut self, request: &[u8]) -> anyhow::Result<()> {
+        if let Some(writer) = &mut self.mut_requests_writer {
+            writer.write_all(&(request.len() as u32).to_le_bytes())?;
+            writer.write_all(request)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the replaced presence if it was another session.
+    fn client_connected(
+        &mut self,
+        client_id: ClientId,
+        session_id: u64,
+        instance_id: InstanceId,
+        simulation_id: SimulationId,
+    ) -> Option<Presence> {
+        let mut connected_since = Utc::now();
+        let mut kicked = None;
+        if let Some(old) = self.presences.remove(&client_id) {
+            if old.session_id == session_id {
+                // Same session changed simulation.
+                connected_since = old.connected_since;
+            } else {
+                log::debug!("{:?} logged in twice. Kicking older session", client_id);
+                kicked = Some(old);
+            }
+        }
+
+        self.presences.insert(
+            client_id,
+            Presence {
+                instance_id,
+                simulation_id,
+                session_id,
+                connected_since,
+            },
+        );
+
+        kicked
+    }
+
+    fn client_disconnected(&mut self, client_id: ClientId, session_id: u64) {
+        if self
+            .presences
+            .get(&client_id)
+            .is_some_and(|presence| presence.session_id == session_id)
+        {
+            self.presences.remove(&client_id);
+        }
+    }
+
+    fn handle_password_result(&mut self, result: PasswordResult) -> anyhow::Result<()> {
+        self.pending_passwords -= 1;
+
+        match result {
+            PasswordResult::Login {
+                client_id,
+                response_token,
+                from,
+            } => {
+                // Client could have been removed while verifying.
+                let client_id = client_id.filter(|client_id| self.clients.contains_key(client_id));
+                self.send_auth_result(client_id, response_token, from);
+            }
+            PasswordResult::Register {
+                username,
+                password_hash,
+                response_token,
+                from,
+            } => {
+                // Username could have been taken while hashing.
+                let client_id = match password_hash {
+                    Ok(password_hash) if !self.username.contains_key(&username) => {
+                        self.log_request(&bin_encode(DatabaseRequest::AddClient {
+                            username: username.clone(),
+                            password_hash: password_hash.clone(),
+                        }))?;
+
+                        Some(self.add_client(username, password_hash))
+                    }
+                    Ok(_) => None,
+                    Err(err) => {
+                        log::error!("Failed to hash password: {}", err);
+                        None
+                    }
+                };
+                self.send_auth_result(client_id, response_token, from);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send_auth_result(
+        &self,
+        client_id: Option<ClientId>,
+        response_token: u64,
+        from: Option<InstanceId>,
+    ) {
+        let Some(instance) = from.and_then(|from| self.instances.get(&from)) else {
+            return;
+        };
+
+        let session_token = client_id.map(|client_id| {
+            SessionToken::new(client_id, self.clients[&client_id].token_generation)
+                .encode(&data().database_key)
+        });
+
+        instance
+            .connection
+            .queue(DatabaseResponse::ClientAuthResult {
+                client_id,
+                response_token,
+                session_token,
+            });
+    }
+
+    fn add_client(&mut self, username: String, password_hash: String) -> ClientId {
+        let client_id = self.next_client_id.next();
+        self.username.insert(username, client_id);
+
+        self.clients.insert(
+            client_id,
+            Client {
+                password_hash: Some(password_hash),
+                ..Default::default()
+            },
+        );
+
+        client_id
+    }
+
+    /// Also notify the new simulation if the ship moved.
+    fn save_ship(
+        &mut self,
+        ship_id: ShipId,
+        simulation_id: SimulationId,
+        save: EntitySave,
+    ) -> anyhow::Result<()> {
+        let new_owner = save.owner;
+
+        let ship = Ship {
+            simulation_id,
+            save,
+        };
+
+        let mut remove_old_owner = None;
+        let mut add_new_owner = None;
+        let mut remove_new_simulation = None;
+        let mut add_new_simulation = None;
+
+        if let Some(old_ship) = self.ships.insert(ship_id, ship) {
+            if old_ship.save.owner != new_owner {
+                remove_old_owner = old_ship.save.owner;
+                add_new_owner = new_owner;
+            }
+
+            if old_ship.simulation_id != simulation_id {
+                remove_new_simulation = Some(old_ship.simulation_id);
+                add_new_simulation = Some(simulation_id);
+            }
+        } else {
+            add_new_owner = new_owner;
+            add_new_simulation = Some(simulation_id);
+
+            if let Some(simulation) = self.simulations.get_mut(&ship_id.origin_simulation_id()) {
+                simulation.last_ship_id = simulation.last_ship_id.max(ship_id);
+            }
+        }
+
+        if let Some(client_id) = remove_old_owner {
+            self.clients
+                .get_mut(&client_id)
+                .context("Ship's previous owner not found")?
+                .ships
+                .remove(&ship_id);
+        }
+        if let Some(client_id) = add_new_owner {
+            self.clients
+                .get_mut(&client_id)
+                .context("Ship's new owner not found")?
+                .ships
+                .insert(ship_id);
+        }
+
+        if let Some(simulation_id) = remove_new_simulation {
+            self.simulations
+                .get_mut(&simulation_id)
+                .context("Ship's previous simulation not found")?
+                .ships
+                .remove(&ship_id);
+        }
+        if let Some(simulation_id) = add_new_simulation {
+            self.simulations
+                .get_mut(&simulation_id)
+                .context("Ship's new simulation not found")?
+                .ships
+                .insert(ship_id);
+
+            // Notify new simulation
+            if let Some(instance) = self
+                .instances
+                .get(&data().simulations[&simulation_id].instance_id)
+            {
+                instance
+                    .connection
+                    .queue(DatabaseResponse::DatabaseSimulationResponse {
+                        to: simulation_id,
+                        response: DatabaseSimulationResponse::ShipEntered {
+                            ship_id,
+                            save: self.ships[&ship_id].save.clone(),
+                        },
+                    });
             }
         }
 
@@ -807,6 +1253,24 @@ impl Database {
                     },
                 );
             }
+            DatabaseQuery::OnlineCount { client_id, from } => {
+                let simulation = self
+                    .presences
+                    .values()
+                    .filter(|presence| presence.simulation_id == *from)
+                    .count();
+
+                self.instances[&from_instance].connection.queue(
+                    DatabaseResponse::DatabaseSimulationResponse {
+                        to: *from,
+                        response: DatabaseSimulationResponse::OnlineCount {
+                            client_id: *client_id,
+                            total: self.presences.len() as u32,
+                            simulation: simulation as u32,
+                        },
+                    },
+                );
+            }
         }
 
         Ok(())
@@ -889,3 +1353,173 @@ fn test_database_serialization() {
     bin_decode::<Database>(&bin_encode(&db)).unwrap();
     serde_json::from_slice::<Database>(&serde_json::to_vec(&db).unwrap()).unwrap();
 }
+
+#[test]
+fn test_save_ships() {
+    let mut db = Database::default();
+    db.prepare();
+
+    let client_id = db.next_client_id.next();
+    db.clients.insert(client_id, Default::default());
+
+    let simulation_a = SimulationId::from_u32(1).unwrap();
+    let simulation_b = SimulationId::from_u32(2).unwrap();
+    let mut next_ship_id = ShipId::new(simulation_a);
+    let ships = (0..2)
+        .map(|_| {
+            (
+                next_ship_id.next(),
+                EntitySave::new(
+                    data().first_ship(),
+                    Some(client_id),
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                ),
+            )
+        })
+        .collect::<Vec<_>>();
+    let moved_ship_id = ships[0].0;
+
+    db.handle_request(
+        &bin_encode(DatabaseRequest::SaveShips {
+            simulation_id: simulation_a,
+            ships: ships.clone(),
+        }),
+        None,
+    )
+    .unwrap();
+    assert_eq!(db.ships.len(), 2);
+    assert_eq!(db.clients[&client_id].ships.len(), 2);
+    assert_eq!(db.simulations[&simulation_a].ships.len(), 2);
+
+    // Saving again in another simulation moves the ship.
+    db.handle_request(
+        &bin_encode(DatabaseRequest::SaveShips {
+            simulation_id: simulation_b,
+            ships: ships[..1].to_vec(),
+        }),
+        None,
+    )
+    .unwrap();
+    assert_eq!(db.ships[&moved_ship_id].simulation_id, simulation_b);
+    assert_eq!(db.simulations[&simulation_a].ships.len(), 1);
+    assert!(db.simulations[&simulation_b].ships.contains(&moved_ship_id));
+}
+
+#[test]
+fn test_password_migration() {
+    let mut db = Database::default();
+
+    let client_id = db.next_client_id.next();
+    db.username.insert("username".to_string(), client_id);
+    db.clients.insert(
+        client_id,
+        Client {
+            password_hash: Some("password".to_string()),
+            ..Default::default()
+        },
+    );
+    db.prepare();
+
+    let password_hash = db.clients[&client_id].password_hash.as_deref();
+    assert_ne!(password_hash, Some("password"));
+    assert!(verify_password("password", password_hash));
+    assert!(!verify_password("wrong", password_hash));
+    assert!(!verify_password("password", None));
+
+    // Already hashed passwords are left as is.
+    let password_hash = password_hash.map(str::to_string);
+    db.prepare();
+    assert_eq!(db.clients[&client_id].password_hash, password_hash);
+}
+
+#[test]
+fn test_presence() {
+    let mut db = Database::default();
+
+    let client_id = ClientId::default();
+    let instance_id = InstanceId::from_u32(1).unwrap();
+    let simulation_a = SimulationId::from_u32(1).unwrap();
+    let simulation_b = SimulationId::from_u32(2).unwrap();
+
+    assert!(db
+        .client_connected(client_id, 1, instance_id, simulation_a)
+        .is_none());
+
+    // Same session moving is not a duplicate.
+    assert!(db
+        .client_connected(client_id, 1, instance_id, simulation_b)
+        .is_none());
+    assert_eq!(db.presences[&client_id].simulation_id, simulation_b);
+
+    // New session kicks the old one.
+    let kicked = db
+        .client_connected(client_id, 2, instance_id, simulation_a)
+        .unwrap();
+    assert_eq!(kicked.session_id, 1);
+
+    // Late disconnect from the kicked session is ignored.
+    db.client_disconnected(client_id, 1);
+    assert_eq!(db.presences[&client_id].session_id, 2);
+
+    db.client_disconnected(client_id, 2);
+    assert!(db.presences.is_empty());
+}
+
+#[test]
+fn test_password_auth() {
+    let mut db = Database::default();
+
+    let auth = |db: &mut Database, login| {
+        db.handle_request(
+            &bin_encode(DatabaseRequest::ClientAuth {
+                login,
+                response_token: 0,
+            }),
+            None,
+        )
+        .unwrap();
+
+        // Hashing happens on the rayon pool.
+        let result = db.password_results.1.recv().unwrap();
+        db.handle_password_result(result).unwrap();
+    };
+
+    auth(
+        &mut db,
+        ClientLoginType::RegisterUsernamePassword {
+            username: "username".to_string(),
+            password: "password".to_string(),
+        },
+    );
+    let client_id = db.username["username"];
+    assert!(verify_password(
+        "password",
+        db.clients[&client_id].password_hash.as_deref()
+    ));
+    assert_eq!(db.pending_passwords, 0);
+
+    // Taken username is refused without hashing.
+    db.handle_request(
+        &bin_encode(DatabaseRequest::ClientAuth {
+            login: ClientLoginType::RegisterUsernamePassword {
+                username: "username".to_string(),
+                password: "other".to_string(),
+            },
+            response_token: 0,
+        }),
+        None,
+    )
+    .unwrap();
+    assert_eq!(db.pending_passwords, 0);
+
+    auth(
+        &mut db,
+        ClientLoginType::LoginUsernamePassword {
+            username: "username".to_string(),
+            password: "password".to_string(),
+        },
+    );
+    assert_eq!(db.pending_passwords, 0);
+}
@@ -3,10 +3,12 @@
 use super::*;
 use entity_data_id_err::*;
 use simulation::entity::EntityData;
+use simulation::turret::WeaponData;
 use std::{
     num::{NonZeroU32, NonZeroU64},
     ops::Deref,
 };
+use weapon_data_id_err::*;
 
 #[derive(Clone, Copy, Serialize, Deserialize)]
 #[serde(try_from = "u32")]
@@ -38,9 +40,9 @@ impl TryFrom<u32> for EntityDataId {
     fn try_from(value: u32) -> Result<Self, Self::Error> {
         data()
             .entities
-            .get(0 as usize)
+            .get(value as usize)
             .map(Self)
-            .ok_or(TryFromEntityDataIdError(0))
+            .ok_or(TryFromEntityDataIdError(value))
     }
 }
 impl From<EntityDataId> for u32 {
@@ -54,6 +56,47 @@ impl std::fmt::Debug for EntityDataId {
     }
 }
 
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "u32")]
+#[serde(into = "u32")]
+pub struct WeaponDataId(pub &'static WeaponData);
+impl Deref for WeaponDataId {
+    type Target = WeaponData;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+pub mod weapon_data_id_err {
+    pub struct TryFromWeaponDataIdError(pub u32);
+    impl std::fmt::Display for TryFromWeaponDataIdError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Invalid weapon data id: {} out of bound", self.0)
+        }
+    }
+}
+impl TryFrom<u32> for WeaponDataId {
+    type Error = TryFromWeaponDataIdError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        data()
+            .weapons
+            .get(value as usize)
+            .map(Self)
+            .ok_or(TryFromWeaponDataIdError(value))
+    }
+}
+impl From<WeaponDataId> for u32 {
+    fn from(ptr: WeaponDataId) -> Self {
+        ptr.id
+    }
+}
+impl std::fmt::Debug for WeaponDataId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.id.fmt(f)
+    }
+}
+
 const ENTITY_ID_START: u64 = 1u64 << 63;
 
 /// - id: 0..63
@@ -1,15 +1,24 @@
 use super::*;
 use connection::*;
 use database::*;
-use simulation::client::Client;
+use session::SessionToken;
+use simulation::client::{Client, ClientOutbound};
 use simulation::*;
+use std::sync::atomic::Ordering;
+
+const CLIENT_CONNECTION_CONFIG: ConnectionConfig = ConnectionConfig {
+    max_queued_bytes: 1024 * 1024,
+    // Smaller packets barely compress.
+    compression_threshold: Some(128),
+};
+const COUNTERS_LOG_INTERVAL: Duration = Duration::from_secs(60);
 
 pub fn _start() {
     let mut state = State::new();
 
     log::info!("Started instance server");
 
-    let mut interval = interval::Interval::new(DT_MS, DT_MS);
+    let mut interval = interval::Interval::new("instance", DT_MS, DT_MS);
     loop {
         interval.step();
 
@@ -20,6 +29,16 @@ pub fn _start() {
     }
 }
 
+pub enum InstanceInbound {
+    /// Move a client to another simulation.
+    /// Client is redirected if the simulation is on another instance.
+    MoveClient {
+        client_id: ClientId,
+        client: Client,
+        to: SimulationId,
+    },
+}
+
 struct State {
     /// Only use in `step`.
     database_inbound: Option<ConnectionInbound>,
@@ -28,14 +47,22 @@ struct State {
     client_listener: ConnectionListener<ClientLogin>,
     logins: AHashMap<u64, (Connection, SimulationId)>,
     next_login_token: u64,
+    /// Session tokens with a lower generation are revoked.
+    token_generations: AHashMap<ClientId, u32>,
 
     simulations: IndexMap<SimulationId, Sender<SimulationInbound>, RandomState>,
+    /// Dropping it stops the scheduler along with its simulations.
+    scheduler: Sender<Simulation>,
+    instance_outbound: Sender<InstanceInbound>,
+    instance_inbound: Receiver<InstanceInbound>,
+
+    next_counters_log: Instant,
 }
 impl State {
     fn new() -> Self {
         let mut result = Err(anyhow::anyhow!("No suitable address found"));
         for (instance_id, instance_data) in data().instances.iter() {
-            result = ConnectionListener::bind(instance_data.addr)
+            result = ConnectionListener::bind(instance_data.addr, CLIENT_CONNECTION_CONFIG)
                 .map(|listener| (*instance_id, listener));
             if result.is_ok() {
                 break;
@@ -46,13 +73,20 @@ impl State {
 
         let (database_outbound, database_inbound) = connect_to_database(instance_id).split();
 
+        let (instance_outbound, instance_inbound) = unbounded();
+
         Self {
             database_inbound: Some(database_inbound),
             database_outbound,
             client_listener,
             logins: Default::default(),
             next_login_token: 0,
+            token_generations: Default::default(),
             simulations: Default::default(),
+            scheduler: scheduler::start(),
+            instance_outbound,
+            instance_inbound,
+            next_counters_log: Instant::now() + COUNTERS_LOG_INTERVAL,
         }
     }
 
@@ -65,6 +99,24 @@ impl State {
                 continue;
             }
 
+            // Session tokens are verified without the database.
+            if let ClientLoginType::SessionToken { token } = &login.login_type {
+                match self.verify_session_token(token) {
+                    Ok(client_id) => {
+                        if let Err(err) =
+                            self.add_client(connection, client_id, login.simulation_id, None)
+                        {
+                            log::warn!("Failed to add client: {}", err);
+                        }
+                    }
+                    Err(err) => {
+                        log::debug!("Refused client login: {}", err);
+                        connection.close("Invalid session token");
+                    }
+                }
+                continue;
+            }
+
             self.database_outbound.queue(DatabaseRequest::ClientAuth {
                 login: login.login_type,
                 response_token: self.next_login_token,
@@ -76,6 +128,13 @@ impl State {
 
         self.database_outbound.flush();
 
+        // Handle simulations requests.
+        while let Ok(inbound) = self.instance_inbound.try_recv() {
+            if let Err(err) = self.handle_instance_inbound(inbound) {
+                log::warn!("Failed to handle simulation request: {}", err);
+            }
+        }
+
         // Handle database responses.
         let mut database_inbound = self.database_inbound.take().unwrap();
         let disconnected = loop {
@@ -91,6 +150,19 @@ impl State {
         };
         self.database_inbound = Some(database_inbound);
 
+        if self.next_counters_log < Instant::now() {
+            self.next_counters_log = Instant::now() + COUNTERS_LOG_INTERVAL;
+            log::info!(
+                "Outbound: {} bytes sent, {} bytes saved by compression, {} stale packets dropped, {} connections evicted",
+                OUTBOUND_COUNTERS.sent_bytes.load(Ordering::Relaxed),
+                OUTBOUND_COUNTERS.compression_saved_bytes.load(Ordering::Relaxed),
+                OUTBOUND_COUNTERS.dropped_packets.load(Ordering::Relaxed),
+                OUTBOUND_COUNTERS
+                    .evicted_connections
+                    .load(Ordering::Relaxed),
+            );
+        }
+
         disconnected
     }
 
@@ -99,6 +171,7 @@ impl State {
             DatabaseResponse::ClientAuthResult {
                 client_id,
                 response_token,
+                session_token,
             } => {
                 let (connection, simulation_id) = self
                     .logins
@@ -106,38 +179,46 @@ impl State {
                     .context("Login should be there")?;
 
                 if let Some(client_id) = client_id {
-                    let sender = self
-                        .simulations
-                        .get(&simulation_id)
-                        .context("Client's requested simulation should be there")?;
-
-                    sender.send(SimulationInbound::NewClient {
-                        client_id,
-                        client: Client::new(connection),
-                    })?;
+                    self.add_client(connection, client_id, simulation_id, session_token)?;
                 } else {
                     connection.close("Failed to authenticate");
                 }
             }
+            DatabaseResponse::KickClient {
+                client_id,
+                session_id,
+            } => {
+                // We don't know which simulation has the client.
+                for sender in self.simulations.values() {
+                    let _ = sender.send(SimulationInbound::KickClient {
+                        client_id,
+                        session_id,
+                    });
+                }
+            }
+            DatabaseResponse::SessionTokensRevoked {
+                client_id,
+                generation,
+            } => {
+                self.token_generations.insert(client_id, generation);
+            }
             DatabaseResponse::HandleSimulation {
                 simulation_id,
                 simulation_save,
                 mut last_ship_id,
             } => {
-                let database_outbound = self.database_outbound.clone();
                 let (simulation_outbound, simulation_inbound) = unbounded();
 
                 self.simulations.insert(simulation_id, simulation_outbound);
 
-                std::thread::spawn(move || {
-                    simulation_loop(Simulation::new(
-                        simulation_id,
-                        database_outbound,
-                        simulation_inbound,
-                        simulation_save,
-                        last_ship_id.next(),
-                    ));
-                });
+                self.scheduler.send(Simulation::new(
+                    simulation_id,
+                    self.database_outbound.clone(),
+                    simulation_inbound,
+                    self.instance_outbound.clone(),
+                    simulation_save,
+                    last_ship_id.next(),
+                ))?;
             }
             DatabaseResponse::SaveAllSimulations => {
                 for sender in self.simulations.values() {
@@ -157,11 +238,80 @@ impl State {
     }
 }
 
-fn simulation_loop(mut simulation: Simulation) {
-    let mut interval = interval::Interval::new(DT_MS, DT_MS * 8);
-    loop {
-        interval.step();
-        simulation.step();
+impl State {
+    fn verify_session_token(&self, token: &[u8]) -> anyhow::Result<ClientId> {
+        let token = SessionToken::decode(token, &data().database_key)?;
+
+        let generation = self
+            .token_generations
+            .get(&token.client_id)
+            .copied()
+            .unwrap_or_default();
+        anyhow::ensure!(token.generation >= generation, "Session token revoked");
+
+        Ok(token.client_id)
+    }
+
+    /// Send an authenticated client to its requested simulation.
+    fn add_client(
+        &self,
+        connection: Connection,
+        client_id: ClientId,
+        simulation_id: SimulationId,
+        session_token: Option<Vec<u8>>,
+    ) -> anyhow::Result<()> {
+        let sender = self
+            .simulations
+            .get(&simulation_id)
+            .context("Client's requested simulation should be there")?;
+
+        let client = Client::new(connection);
+        if let Some(token) = session_token {
+            client.queue(ClientOutbound::SessionToken { token });
+        }
+
+        sender.send(SimulationInbound::NewClient { client_id, client })?;
+
+        Ok(())
+    }
+
+    fn handle_instance_inbound(&mut self, inbound: InstanceInbound) -> anyhow::Result<()> {
+        match inbound {
+            InstanceInbound::MoveClient {
+                client_id,
+                mut client,
+                to,
+            } => {
+                client.clear();
+
+                if let Some(sender) = self.simulations.get(&to) {
+                    sender.send(SimulationInbound::NewClient { client_id, client })?;
+                } else {
+                    let simulation_data = data()
+                        .simulations
+                        .get(&to)
+                        .context("Client's requested simulation should exist")?;
+                    let instance_data = data()
+                        .instances
+                        .get(&simulation_data.instance_id)
+                        .context("Simulation's instance should exist")?;
+
+                    client.queue(ClientOutbound::Redirect {
+                        addr: instance_data.addr.to_string(),
+                        simulation_id: to,
+                    });
+                    client.close("Redirected to another instance");
+
+                    self.database_outbound
+                        .queue(DatabaseRequest::ClientDisconnected {
+                            client_id,
+                            session_id: client.session_id,
+                        });
+                }
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -169,6 +319,9 @@ fn simulation_loop(mut simulation: Simulation) {
 struct ClientLogin {
     simulation_id: SimulationId,
     login_type: ClientLoginType,
+    /// Optional trailing byte, older clients don't send it and can't read frame bytes.
+    #[serde(skip)]
+    compression: bool,
 }
 impl Packet for ClientLogin {
     fn serialize(self) -> Vec<u8> {
@@ -176,12 +329,28 @@ impl Packet for ClientLogin {
     }
 
     fn parse(buf: Vec<u8>) -> anyhow::Result<Self> {
-        bin_decode(&buf)
+        let (mut login, rest) = postcard::take_from_bytes::<Self>(&buf)?;
+        login.compression = rest.first() == Some(&1);
+        Ok(login)
+    }
+
+    fn compression(&self) -> bool {
+        self.compression
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ClientLoginType {
-    LoginUsernamePassword { username: String, password: String },
-    RegisterUsernamePassword { username: String, password: String },
+    LoginUsernamePassword {
+        username: String,
+        password: String,
+    },
+    RegisterUsernamePassword {
+        username: String,
+        password: String,
+    },
+    /// Token previously received with [ClientOutbound::SessionToken].
+    SessionToken {
+        token: Vec<u8>,
+    },
 }
@@ -1,13 +1,18 @@
 use super::*;
 
 pub struct Interval {
+    behind: prometheus::IntCounter,
     internal_time: Instant,
     max_difference: Duration,
     target_interval: Duration,
 }
 impl Interval {
-    pub fn new(interval: u64, max_difference: u64) -> Self {
+    /// `name` labels the metrics.
+    pub fn new(name: &str, interval: u64, max_difference: u64) -> Self {
         Self {
+            behind: metrics::metrics()
+                .interval_behind
+                .with_label_values(&[name]),
             internal_time: Instant::now(),
             max_difference: Duration::from_millis(max_difference),
             target_interval: Duration::from_millis(interval),
@@ -19,7 +24,10 @@ impl Interval {
 
         self.internal_time += self.target_interval;
 
-        let behind = now - self.internal_time;
+        let behind = now.saturating_duration_since(self.internal_time);
+        if !behind.is_zero() {
+            self.behind.inc();
+        }
         if behind > self.max_difference {
             log::debug!(
                 "Interval behind by {}ms which is more than maximum of {}ms",
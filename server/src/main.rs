@@ -6,6 +6,9 @@ mod ids;
 mod instance;
 mod interval;
 mod logger;
+mod metrics;
+mod scheduler;
+mod session;
 mod simulation;
 mod util;
 
@@ -28,14 +31,14 @@ use std::time::{Duration, Instant};
 // // TODO: add feature for database/instance
 // // TODO: Mini app which compile and relauches instance and database if they exit
 // // TODO: Private key taken from file
-// TODO: Websocket encryption
+// // TODO: Websocket encryption
 // TODO: Find a proper name + rename paths and project
 
 // TODO: Database:
 // Add starting ship database resquest (if no ship)
-// keep track of logged-in client
+// // keep track of logged-in client
 // only send fleet update to client when something changes
-// only store hashed password
+// // only store hashed password
 // // Check invariants on startup (armor cell size, username -> client, all simulations from data exist)
 // // Do not store encoded value in database
 // // Add global time tracking
@@ -46,16 +49,16 @@ use std::time::{Duration, Instant};
 
 // TODO: Instance:
 // // remove client outbound (only simulation has any)
-// add fast way for client to change simulation on same instance without reconnect
+// // add fast way for client to change simulation on same instance without reconnect
 // simulation packets shouldn't need to pass through instance
 
 // TODO: Simulation:
-// add ships to intermitent simulation save
+// // add ships to intermitent simulation save
 // Keep track of what data client has and send as needed instead of waiting for query
-// Add entity detection and detector range
+// // Add entity detection and detector range
 // remove uneeded derives
 // // has its own id range for entity/ship based on sim id
-// Variable dt (for "sleeping" simulations)
+// // Variable dt (for "sleeping" simulations)
 // change collision groups to an enum
 // projectile: simple vec + manual query. Updated client side
 // // add ignore group (no collision between entities in same group)
@@ -101,6 +104,10 @@ fn main() {
 
     data::load_data();
 
+    if let Some(addr) = data().metrics_addr {
+        metrics::start_exporter(addr);
+    }
+
     #[cfg(all(feature = "database", not(feature = "instance")))]
     {
         database::_start();
@@ -111,7 +118,7 @@ fn main() {
     }
     #[cfg(all(feature = "database", feature = "instance"))]
     {
-        std::thread::spawn(|| database::_start());
+        std::thread::spawn(database::_start);
         instance::_start();
     }
 }
@@ -0,0 +1,299 @@
+use super::*;
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+static _METRICS: std::sync::OnceLock<Metrics> = std::sync::OnceLock::new();
+pub fn metrics() -> &'static Metrics {
+    _METRICS.get_or_init(Metrics::new)
+}
+
+pub struct Metrics {
+    registry: Registry,
+
+    /// Labels: loop.
+    pub interval_behind: IntCounterVec,
+
+    /// Labels: simulation, phase.
+    tick_phase_seconds: HistogramVec,
+    /// Labels: simulation.
+    simulation_entities: IntGaugeVec,
+    simulation_clients: IntGaugeVec,
+    simulation_colliders: IntGaugeVec,
+    simulation_sleeping: IntGaugeVec,
+    simulation_behind: IntCounterVec,
+    /// Bytes sent to each client every tick.
+    client_sent_bytes: HistogramVec,
+
+    outbound_sent_bytes: IntCounter,
+    outbound_dropped_packets: IntCounter,
+    outbound_evicted_connections: IntCounter,
+    outbound_compression_saved_bytes: IntCounter,
+
+    pub database_clients: IntGauge,
+    pub database_online_clients: IntGauge,
+    pub database_ships: IntGauge,
+    pub database_instances: IntGauge,
+    pub database_step_seconds: Histogram,
+}
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("eos".to_string()), None).unwrap();
+
+        let tick_buckets = exponential_buckets(0.00001, 2.0, 16).unwrap();
+
+        let result = Self {
+            interval_behind: IntCounterVec::new(
+                Opts::new("interval_behind_total", "Loop steps which started late"),
+                &["loop"],
+            )
+            .unwrap(),
+            tick_phase_seconds: HistogramVec::new(
+                HistogramOpts::new("tick_phase_seconds", "Time spent in each step phase")
+                    .buckets(tick_buckets.clone()),
+                &["simulation", "phase"],
+            )
+            .unwrap(),
+            simulation_entities: IntGaugeVec::new(
+                Opts::new("simulation_entities", "Entities in simulation"),
+                &["simulation"],
+            )
+            .unwrap(),
+            simulation_clients: IntGaugeVec::new(
+                Opts::new("simulation_clients", "Clients in simulation"),
+                &["simulation"],
+            )
+            .unwrap(),
+            simulation_colliders: IntGaugeVec::new(
+                Opts::new("simulation_colliders", "Colliders in simulation"),
+                &["simulation"],
+            )
+            .unwrap(),
+            simulation_sleeping: IntGaugeVec::new(
+                Opts::new("simulation_sleeping", "1 if simulation is sleeping"),
+                &["simulation"],
+            )
+            .unwrap(),
+            simulation_behind: IntCounterVec::new(
+                Opts::new("simulation_behind_total", "Times simulation skipped ticks"),
+                &["simulation"],
+            )
+            .unwrap(),
+            client_sent_bytes: HistogramVec::new(
+                HistogramOpts::new("client_sent_bytes", "Bytes sent to a client every tick")
+                    .buckets(exponential_buckets(64.0, 2.0, 12).unwrap()),
+                &["simulation"],
+            )
+            .unwrap(),
+            outbound_sent_bytes: IntCounter::new(
+                "outbound_sent_bytes_total",
+                "Bytes sent over every connection",
+            )
+            .unwrap(),
+            outbound_dropped_packets: IntCounter::new(
+                "outbound_dropped_packets_total",
+                "Replaceable packets which were never sent",
+            )
+            .unwrap(),
+            outbound_evicted_connections: IntCounter::new(
+                "outbound_evicted_connections_total",
+                "Connections closed for being too far behind",
+            )
+            .unwrap(),
+            outbound_compression_saved_bytes: IntCounter::new(
+                "outbound_compression_saved_bytes_total",
+                "Bytes saved by compression",
+            )
+            .unwrap(),
+            database_clients: IntGauge::new("database_clients", "Registered clients").unwrap(),
+            database_online_clients: IntGauge::new(
+                "database_online_clients",
+                "Clients connected to an instance",
+            )
+            .unwrap(),
+            database_ships: IntGauge::new("database_ships", "Ships in database").unwrap(),
+            database_instances: IntGauge::new(
+                "database_instances",
+                "Instances connected to database",
+            )
+            .unwrap(),
+            database_step_seconds: Histogram::with_opts(
+                HistogramOpts::new("database_step_seconds", "Time spent in a database step")
+                    .buckets(tick_buckets),
+            )
+            .unwrap(),
+            registry,
+        };
+
+        let collectors: [Box<dyn prometheus::core::Collector>; 17] = [
+            Box::new(result.interval_behind.clone()),
+            Box::new(result.tick_phase_seconds.clone()),
+            Box::new(result.simulation_entities.clone()),
+            Box::new(result.simulation_clients.clone()),
+            Box::new(result.simulation_colliders.clone()),
+            Box::new(result.simulation_sleeping.clone()),
+            Box::new(result.simulation_behind.clone()),
+            Box::new(result.client_sent_bytes.clone()),
+            Box::new(result.outbound_sent_bytes.clone()),
+            Box::new(result.outbound_dropped_packets.clone()),
+            Box::new(result.outbound_evicted_connections.clone()),
+            Box::new(result.outbound_compression_saved_bytes.clone()),
+            Box::new(result.database_clients.clone()),
+            Box::new(result.database_online_clients.clone()),
+            Box::new(result.database_ships.clone()),
+            Box::new(result.database_instances.clone()),
+            Box::new(result.database_step_seconds.clone()),
+        ];
+        for collector in collectors {
+            result.registry.register(collector).unwrap();
+        }
+
+        result
+    }
+
+    /// Resolve labels once as simulations update every tick.
+    pub fn simulation(&self, simulation_id: SimulationId) -> SimulationMetrics {
+        let label = simulation_id.as_u32().to_string();
+        let phase = |phase: &str| {
+            self.tick_phase_seconds
+                .with_label_values(&[label.as_str(), phase])
+        };
+
+        SimulationMetrics {
+            inbound: phase("inbound"),
+            client_packets: phase("client_packets"),
+            physics: phase("physics"),
+            entities: phase("entities"),
+            projectiles: phase("projectiles"),
+            update_clients: phase("update_clients"),
+            step: phase("step"),
+            entity_count: self.simulation_entities.with_label_values(&[&label]),
+            client_count: self.simulation_clients.with_label_values(&[&label]),
+            collider_count: self.simulation_colliders.with_label_values(&[&label]),
+            sleeping: self.simulation_sleeping.with_label_values(&[&label]),
+            behind: self.simulation_behind.with_label_values(&[&label]),
+            client_sent_bytes: self.client_sent_bytes.with_label_values(&[&label]),
+        }
+    }
+
+    /// Text exposition format of every metric.
+    pub fn encode(&self) -> Vec<u8> {
+        let sync = |counter: &IntCounter, value: &AtomicU64| {
+            let value = value.load(Ordering::Relaxed);
+            counter.inc_by(value.saturating_sub(counter.get()));
+        };
+        sync(&self.outbound_sent_bytes, &OUTBOUND_COUNTERS.sent_bytes);
+        sync(
+            &self.outbound_dropped_packets,
+            &OUTBOUND_COUNTERS.dropped_packets,
+        );
+        sync(
+            &self.outbound_evicted_connections,
+            &OUTBOUND_COUNTERS.evicted_connections,
+        );
+        sync(
+            &self.outbound_compression_saved_bytes,
+            &OUTBOUND_COUNTERS.compression_saved_bytes,
+        );
+
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .unwrap();
+        buf
+    }
+}
+
+pub struct SimulationMetrics {
+    pub inbound: Histogram,
+    pub client_packets: Histogram,
+    pub physics: Histogram,
+    pub entities: Histogram,
+    pub projectiles: Histogram,
+    pub update_clients: Histogram,
+    pub step: Histogram,
+    pub entity_count: IntGauge,
+    pub client_count: IntGauge,
+    pub collider_count: IntGauge,
+    pub sleeping: IntGauge,
+    pub behind: IntCounter,
+    pub client_sent_bytes: Histogram,
+}
+
+/// Observe the time between laps.
+pub struct Stopwatch(Instant);
+impl Stopwatch {
+    pub fn start() -> Self {
+        Self(Instant::now())
+    }
+
+    pub fn lap(&mut self, histogram: &Histogram) {
+        let now = Instant::now();
+        histogram.observe((now - self.0).as_secs_f64());
+        self.0 = now;
+    }
+}
+
+// ####################################################################################
+// ############## EXPORTER ############################################################
+// ####################################################################################
+
+/// Serve metrics over http on every path.
+pub fn start_exporter(addr: SocketAddr) {
+    tokio().spawn(async move {
+        let listener = match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                log::warn!("Failed to bind metrics exporter to {}: {}", addr, err);
+                return;
+            }
+        };
+        log::info!("Metrics exported on http://{}/metrics", addr);
+
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            tokio::spawn(async move {
+                if let Err(err) = serve(stream).await {
+                    log::debug!("Failed to serve metrics: {}", err);
+                }
+            });
+        }
+    });
+}
+
+async fn serve(mut stream: tokio::net::TcpStream) -> anyhow::Result<()> {
+    // Only reading the request line. Everything gets the same response.
+    let mut request = [0; 1024];
+    let _ = stream.read(&mut request).await?;
+
+    let body = metrics().encode();
+    let header = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+// ####################################################################################
+// ############## TEST ################################################################
+// ####################################################################################
+
+#[test]
+fn test_metrics_encode() {
+    let simulation = metrics().simulation(SimulationId::from_u32(7).unwrap());
+    simulation.entity_count.set(3);
+    Stopwatch::start().lap(&simulation.physics);
+
+    let text = String::from_utf8(metrics().encode()).unwrap();
+    assert!(text.contains("eos_simulation_entities{simulation=\"7\"} 3"));
+    assert!(text.contains("eos_tick_phase_seconds_count{phase=\"physics\",simulation=\"7\"} 1"));
+}
@@ -0,0 +1,180 @@
+use super::*;
+use rayon::prelude::*;
+use simulation::*;
+
+/// Simulation behind by more than this skip ticks.
+const MAX_BEHIND: Duration = Duration::from_millis(DT_MS * 8);
+const COST_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Step simulations sent to the returned sender on a shared thread pool.
+///
+/// Stops when the sender is dropped, which the instance does when it restarts.
+/// Simulations are saved before being dropped.
+pub fn start() -> Sender<Simulation> {
+    let (sender, receiver) = unbounded();
+
+    let num_threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .thread_name(|i| format!("simulation-{}", i))
+        .build()
+        .unwrap();
+
+    std::thread::spawn(move || {
+        Scheduler {
+            new_simulations: receiver,
+            pool,
+            scheduled: Default::default(),
+            next_cost_log: Instant::now() + COST_LOG_INTERVAL,
+        }
+        .run();
+    });
+
+    sender
+}
+
+struct Scheduler {
+    new_simulations: Receiver<Simulation>,
+    pool: rayon::ThreadPool,
+    scheduled: Vec<Scheduled>,
+    next_cost_log: Instant,
+}
+impl Scheduler {
+    fn run(mut self) {
+        log::info!(
+            "Scheduling simulations on {} threads",
+            self.pool.current_num_threads()
+        );
+
+        loop {
+            let now = Instant::now();
+
+            let mut due: Vec<&mut Scheduled> = self
+                .scheduled
+                .iter_mut()
+                .filter(|scheduled| scheduled.is_due(now))
+                .collect();
+            self.pool
+                .install(|| due.par_iter_mut().for_each(|scheduled| scheduled.step(now)));
+
+            if self.next_cost_log < now {
+                self.next_cost_log = now + COST_LOG_INTERVAL;
+                for scheduled in self.scheduled.iter_mut() {
+                    scheduled.cost.log(scheduled.simulation.simulation_id());
+                }
+            }
+
+            // Also wake up after at most one tick to poll sleeping simulations.
+            let next_tick = self
+                .scheduled
+                .iter()
+                .map(|scheduled| scheduled.next_tick)
+                .min()
+                .unwrap_or(now + Duration::from_millis(DT_MS))
+                .clamp(now, now + Duration::from_millis(DT_MS));
+            match self.new_simulations.recv_deadline(next_tick) {
+                Ok(simulation) => {
+                    log::debug!("Scheduled {:?}", simulation.simulation_id());
+                    self.scheduled.push(Scheduled::new(simulation));
+                }
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => {}
+                Err(crossbeam_channel::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        for scheduled in self.scheduled.iter_mut() {
+            scheduled.simulation.save();
+        }
+        log::info!("Stopped {} simulations", self.scheduled.len());
+    }
+}
+
+struct Scheduled {
+    simulation: Simulation,
+    next_tick: Instant,
+    last_step: Instant,
+    cost: TickCost,
+}
+impl Scheduled {
+    fn new(simulation: Simulation) -> Self {
+        let now = Instant::now();
+        Self {
+            simulation,
+            next_tick: now,
+            last_step: now,
+            cost: Default::default(),
+        }
+    }
+
+    fn is_due(&self, now: Instant) -> bool {
+        self.next_tick <= now
+            || (self.simulation.mode() == SimulationMode::Sleeping && self.simulation.has_inbound())
+    }
+
+    fn step(&mut self, now: Instant) {
+        let was_sleeping = self.simulation.mode() == SimulationMode::Sleeping;
+        if was_sleeping {
+            let slept = now.saturating_duration_since(self.last_step);
+            self.simulation
+                .set_slept_ticks(slept.as_millis() as u64 / DT_MS);
+        }
+
+        let start = Instant::now();
+        self.simulation.step();
+        self.cost.add(start.elapsed());
+        self.last_step = now;
+
+        match self.simulation.mode() {
+            SimulationMode::Active if was_sleeping => {
+                self.next_tick = now + Duration::from_millis(DT_MS);
+            }
+            SimulationMode::Active => {
+                self.next_tick += Duration::from_millis(DT_MS);
+
+                let behind = now.saturating_duration_since(self.next_tick);
+                if behind > MAX_BEHIND {
+                    self.simulation.metrics().behind.inc();
+                    log::debug!(
+                        "{:?} behind by {}ms",
+                        self.simulation.simulation_id(),
+                        behind.as_millis()
+                    );
+                    self.next_tick = now - MAX_BEHIND;
+                }
+            }
+            SimulationMode::Sleeping => {
+                self.next_tick = now + Duration::from_millis(DT_MS * SLEEP_TICKS);
+            }
+        }
+    }
+}
+
+/// Time spent stepping a simulation since last logged.
+#[derive(Default)]
+struct TickCost {
+    ticks: u32,
+    total: Duration,
+    max: Duration,
+}
+impl TickCost {
+    fn add(&mut self, cost: Duration) {
+        self.ticks += 1;
+        self.total += cost;
+        self.max = self.max.max(cost);
+    }
+
+    fn log(&mut self, simulation_id: SimulationId) {
+        if self.ticks > 0 {
+            log::info!(
+                "{:?}: {} ticks, {}us average, {}us max",
+                simulation_id,
+                self.ticks,
+                self.total.as_micros() / self.ticks as u128,
+                self.max.as_micros()
+            );
+        }
+        *self = Default::default();
+    }
+}
@@ -0,0 +1,98 @@
+use super::*;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How long a session token stays valid after being issued.
+pub const SESSION_TOKEN_DURATION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+const SIGNATURE_SIZE: usize = 32;
+
+/// Issued by the database after a successful authentication.
+/// Can be verified by any instance without asking the database.
+///
+/// Encoded as the postcard payload followed by its HMAC-SHA256 signature
+/// using the shared database key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionToken {
+    pub client_id: ClientId,
+    /// Unix timestamp in seconds.
+    pub expire_at: u64,
+    /// Tokens with a generation lower than the client's current one are revoked.
+    pub generation: u32,
+}
+impl SessionToken {
+    pub fn new(client_id: ClientId, generation: u32) -> Self {
+        Self {
+            client_id,
+            expire_at: unix_now() + SESSION_TOKEN_DURATION.as_secs(),
+            generation,
+        }
+    }
+
+    pub fn encode(&self, key: &[u8]) -> Vec<u8> {
+        let mut buf = bin_encode(self);
+        let signature = mac(key, &buf).finalize().into_bytes();
+        buf.extend_from_slice(&signature);
+        buf
+    }
+
+    /// Checks the signature and expiration, but not revocation.
+    pub fn decode(buf: &[u8], key: &[u8]) -> anyhow::Result<Self> {
+        let payload_len = buf
+            .len()
+            .checked_sub(SIGNATURE_SIZE)
+            .context("Session token too short")?;
+        let (payload, signature) = buf.split_at(payload_len);
+
+        // Constant-time comparison.
+        mac(key, payload)
+            .verify_slice(signature)
+            .map_err(|_| anyhow::anyhow!("Invalid session token signature"))?;
+
+        let token: Self = bin_decode(payload)?;
+        anyhow::ensure!(token.expire_at > unix_now(), "Session token expired");
+
+        Ok(token)
+    }
+}
+
+fn mac(key: &[u8], payload: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("hmac accepts any key size");
+    mac.update(payload);
+    mac
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// ####################################################################################
+// ############## TEST ################################################################
+// ####################################################################################
+
+#[test]
+fn test_session_token() {
+    let key = b"key";
+    let token = SessionToken::new(ClientId::default(), 3);
+
+    let mut buf = token.encode(key);
+    assert_eq!(SessionToken::decode(&buf, key).unwrap(), token);
+    assert!(SessionToken::decode(&buf, b"other key").is_err());
+    assert!(SessionToken::decode(&buf[..SIGNATURE_SIZE - 1], key).is_err());
+
+    // Tampered payload.
+    buf[0] ^= 1;
+    assert!(SessionToken::decode(&buf, key).is_err());
+
+    let expired = SessionToken {
+        expire_at: unix_now() - 1,
+        ..token
+    };
+    assert!(SessionToken::decode(&expired.encode(key), key).is_err());
+}
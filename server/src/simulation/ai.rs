@@ -0,0 +1,612 @@
+use super::*;
+
+/// How often ai look for hostiles. Acting happens every step.
+const THINK_INTERVAL: f64 = 0.5;
+/// Engaged target is dropped past this multiple of engage range.
+const DISENGAGE_MULTIPLIER: f32 = 1.5;
+/// Distance kept from an escorted entity.
+const ESCORT_DISTANCE: f32 = 6.0;
+/// A new patrol waypoint is picked when this close.
+const WAYPOINT_REACHED: f32 = 2.0;
+
+/// Ship ai tuning. Taken from the entity data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AiShipData {
+    /// Hostiles closer than this are engaged.
+    pub engage_range: f32,
+    /// Distance kept from engaged target.
+    pub preferred_range: f32,
+    /// Flee from hostiles when hull is below this ratio.
+    pub flee_hull: f32,
+    /// Patrol around spawn point when more than 0. Otherwise idle.
+    pub patrol_radius: f32,
+}
+impl AiShipData {
+    pub fn verify(&mut self) {
+        self.engage_range = self.engage_range.max(0.0);
+        self.preferred_range = self.preferred_range.clamp(0.0, self.engage_range);
+        self.flee_hull = self.flee_hull.clamp(0.0, 1.0);
+        self.patrol_radius = self.patrol_radius.max(0.0);
+    }
+}
+impl Default for AiShipData {
+    fn default() -> Self {
+        Self {
+            engage_range: 30.0,
+            preferred_range: 10.0,
+            flee_hull: 0.2,
+            patrol_radius: 0.0,
+        }
+    }
+}
+
+/// What the ai does when not fighting.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+pub enum AiTask {
+    /// Stay in place.
+    #[default]
+    Idle,
+    /// Move between random waypoints around center.
+    Patrol {
+        center: Vector2<f32>,
+        radius: f32,
+        waypoint: Vector2<f32>,
+    },
+    /// Stay close to an entity. Becomes idle if the entity is gone.
+    Escort { entity_id: EntityId },
+}
+
+/// Overrides the task when hostiles are around.
+#[derive(Debug, Clone, Copy, Default)]
+enum AiState {
+    #[default]
+    Task,
+    Engage {
+        target: EntityId,
+    },
+    Flee {
+        from: EntityId,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AiShip {
+    pub task: AiTask,
+    state: AiState,
+    next_think: f64,
+    /// Considered for engaging on next think.
+    last_attacker: Option<EntityId>,
+}
+impl AiShip {
+    pub fn new(data: &AiShipData, translation: Vector2<f32>) -> Self {
+        let task = if data.patrol_radius > 0.0 {
+            AiTask::Patrol {
+                center: translation,
+                radius: data.patrol_radius,
+                waypoint: translation,
+            }
+        } else {
+            AiTask::Idle
+        };
+
+        Self {
+            task,
+            state: AiState::Task,
+            next_think: 0.0,
+            last_attacker: None,
+        }
+    }
+
+    pub fn on_event(&mut self, event: &SimulationEvent) {
+        if let SimulationEvent::Damaged {
+            by: Some(by),
+            amount,
+            ..
+        } = event
+        {
+            // Grazing contacts are not an attack.
+            if *amount <= 0.0 {
+                return;
+            }
+
+            self.last_attacker = Some(*by);
+            // React sooner.
+            self.next_think = self.next_think.min(0.0);
+        }
+    }
+
+    pub fn with_task(task: AiTask) -> Self {
+        Self {
+            task,
+            ..Default::default()
+        }
+    }
+
+    /// Write the entity's wishes.
+    ///
+    /// Orders take precedence over the task.
+    pub fn update(&mut self, sim: &mut Simulation, entity_idx: usize) {
+        if sim.sim_time >= self.next_think {
+            // Stagger thinking between entities.
+            self.next_think = sim.sim_time + THINK_INTERVAL * thread_rng().gen_range(0.8..1.2);
+            self.think(sim, entity_idx);
+        }
+
+        let (&entity_id, entity) = sim.entities.get_index(entity_idx).unwrap();
+        let translation = *sim.physics.body(entity.rb).translation();
+        let data = &entity.data.0.ai;
+
+        // Itself counts as missing so orders about it are done.
+        let translation_of = |other_id: EntityId| {
+            sim.entities
+                .get(&other_id)
+                .filter(|_| other_id != entity_id)
+                .map(|entity| *sim.physics.body(entity.rb).translation())
+        };
+        // Only wrecks can be salvaged.
+        let salvage = match entity.orders.front() {
+            Some(Order::Salvage { entity_id }) => sim
+                .entities
+                .get(entity_id)
+                .filter(|wreck| wreck.wreck)
+                .map(|wreck| {
+                    (
+                        *sim.physics.body(wreck.rb).translation(),
+                        salvage_distance(&entity.data, &wreck.data),
+                    )
+                }),
+            _ => None,
+        };
+        let other_translation = match self.state {
+            AiState::Engage { target } | AiState::Flee { from: target } => translation_of(target),
+            AiState::Task => match entity.orders.front() {
+                Some(
+                    Order::Follow { entity_id }
+                    | Order::Attack { entity_id }
+                    | Order::Defend { entity_id },
+                ) => translation_of(*entity_id),
+                Some(Order::Salvage { .. }) => salvage.map(|(translation, _)| translation),
+                Some(_) => None,
+                None => match self.task {
+                    AiTask::Escort { entity_id } => translation_of(entity_id),
+                    _ => None,
+                },
+            },
+        };
+
+        let entity = &mut sim.entities[entity_idx];
+        entity.wish_salvage = None;
+        match self.state {
+            AiState::Engage { target } => {
+                let Some(target_translation) = other_translation else {
+                    self.state = AiState::Task;
+                    return;
+                };
+
+                let keep_at = target_translation
+                    + (translation - target_translation)
+                        .try_normalize(0.01)
+                        .unwrap_or(vector![0.0, -1.0])
+                        * data.preferred_range;
+
+                entity.target = Some(target);
+                entity.wish_linvel = WishLinVel::PositionSmooth(keep_at);
+                entity.wish_angvel = WishAngVel::AimSmooth(target_translation);
+                entity.wish_aim = WishAim::Position(target_translation);
+                entity.wish_fire = true;
+                return;
+            }
+            AiState::Flee { .. } => {
+                let Some(from_translation) = other_translation else {
+                    self.state = AiState::Task;
+                    return;
+                };
+
+                let away = (translation - from_translation)
+                    .try_normalize(0.01)
+                    .unwrap_or(vector![0.0, -1.0]);
+
+                entity.wish_linvel = WishLinVel::ForceAbsolute(away);
+                entity.wish_angvel = WishAngVel::AimSmooth(translation + away);
+                // Still shoot back if turrets can reach.
+                entity.wish_aim = WishAim::Position(from_translation);
+                entity.wish_fire = true;
+                return;
+            }
+            AiState::Task => {}
+        }
+
+        entity.wish_aim = WishAim::Rest;
+        entity.wish_fire = false;
+
+        if let Some(order) = entity.orders.front_mut() {
+            let done = match order {
+                Order::MoveTo { position } => {
+                    entity.wish_linvel = WishLinVel::PositionSmooth(*position);
+                    entity.wish_angvel = WishAngVel::AimSmooth(*position);
+                    (*position - translation).magnitude_squared() < WAYPOINT_REACHED.powi(2)
+                }
+                Order::Follow { .. } | Order::Defend { .. } => {
+                    if let Some(escorted_translation) = other_translation {
+                        escort(entity, translation, escorted_translation);
+                        false
+                    } else {
+                        true
+                    }
+                }
+                Order::Attack { entity_id } => {
+                    // Engage without waiting for the next think.
+                    if other_translation.is_some() {
+                        self.state = AiState::Engage { target: *entity_id };
+                        false
+                    } else {
+                        true
+                    }
+                }
+                Order::Salvage { entity_id } => {
+                    if let Some((wreck_translation, distance)) = salvage {
+                        let keep_at = wreck_translation
+                            + (translation - wreck_translation)
+                                .try_normalize(0.01)
+                                .unwrap_or(vector![0.0, 1.0])
+                                * distance
+                                * 0.8;
+                        entity.wish_linvel = WishLinVel::PositionSmooth(keep_at);
+                        entity.wish_angvel = WishAngVel::AimSmooth(wreck_translation);
+                        entity.wish_salvage = Some(*entity_id);
+                        false
+                    } else {
+                        true
+                    }
+                }
+                Order::Patrol { waypoints, next } => {
+                    let waypoint = waypoints[*next % waypoints.len()];
+                    if (waypoint - translation).magnitude_squared() < WAYPOINT_REACHED.powi(2) {
+                        *next = (*next + 1) % waypoints.len();
+                    }
+                    entity.wish_linvel = WishLinVel::PositionSmooth(waypoint);
+                    entity.wish_angvel = WishAngVel::AimSmooth(waypoint);
+                    false
+                }
+            };
+
+            if done {
+                entity.orders.pop_front();
+                sim.send_orders(entity_id);
+            }
+            return;
+        }
+
+        match &mut self.task {
+            AiTask::Idle => {
+                entity.wish_linvel = WishLinVel::Cancel;
+                entity.wish_angvel = WishAngVel::Stop;
+            }
+            AiTask::Patrol {
+                center,
+                radius,
+                waypoint,
+            } => {
+                if (*waypoint - translation).magnitude_squared() < WAYPOINT_REACHED.powi(2) {
+                    let angle = thread_rng().gen_range(0.0..TAU);
+                    let distance = *radius * thread_rng().gen::<f32>().sqrt();
+                    *waypoint = *center + vector![angle.cos(), angle.sin()] * distance;
+                }
+
+                entity.wish_linvel = WishLinVel::PositionSmooth(*waypoint);
+                entity.wish_angvel = WishAngVel::AimSmooth(*waypoint);
+            }
+            AiTask::Escort { .. } => {
+                if let Some(escorted_translation) = other_translation {
+                    escort(entity, translation, escorted_translation);
+                } else {
+                    self.task = AiTask::Idle;
+                }
+            }
+        }
+    }
+
+    fn think(&mut self, sim: &Simulation, entity_idx: usize) {
+        let entity = &sim.entities[entity_idx];
+        let translation = *sim.physics.body(entity.rb).translation();
+        let data = &entity.data.ai;
+
+        let ordered_target = match entity.orders.front() {
+            Some(Order::Attack { entity_id }) => Some(*entity_id),
+            _ => None,
+        };
+
+        // Keep the current target while it is not too far.
+        // Ordered target is kept at any range.
+        if let AiState::Engage { target } | AiState::Flee { from: target } = self.state {
+            let keep = sim.entities.get(&target).is_some_and(|target_entity| {
+                Some(target) == ordered_target
+                    || (sim.physics.body(target_entity.rb).translation() - translation).magnitude()
+                        <= data.engage_range * DISENGAGE_MULTIPLIER
+            });
+            if !keep {
+                self.state = AiState::Task;
+            }
+        }
+
+        if let AiState::Task = self.state {
+            if let Some(target) = ordered_target.filter(|target| sim.entities.contains_key(target))
+            {
+                self.state = AiState::Engage { target };
+            } else {
+                // Defending look for hostiles around the defended entity instead.
+                let center = match entity.orders.front() {
+                    Some(Order::Defend { entity_id }) => sim
+                        .entities
+                        .get(entity_id)
+                        .map(|defended| *sim.physics.body(defended.rb).translation())
+                        .unwrap_or(translation),
+                    _ => translation,
+                };
+
+                // Fight back when attacked from outside engage range.
+                let attacker = self.last_attacker.filter(|attacker| {
+                    sim.entities
+                        .get(attacker)
+                        .is_some_and(|attacker| !attacker.wreck && attacker.owner != entity.owner)
+                });
+
+                if let Some(hostile) =
+                    nearest_hostile(sim, entity, center, data.engage_range).or(attacker)
+                {
+                    self.state = AiState::Engage { target: hostile };
+                }
+            }
+        }
+
+        self.last_attacker = None;
+
+        if let AiState::Engage { target } = self.state {
+            if entity.hull_ratio() < data.flee_hull {
+                self.state = AiState::Flee { from: target };
+            }
+        }
+    }
+}
+
+/// Stay at a distance from an entity while facing it.
+fn escort(entity: &mut Entity, translation: Vector2<f32>, escorted_translation: Vector2<f32>) {
+    let keep_at = escorted_translation
+        + (translation - escorted_translation)
+            .try_normalize(0.01)
+            .unwrap_or(vector![0.0, 1.0])
+            * ESCORT_DISTANCE;
+
+    entity.wish_linvel = WishLinVel::PositionSmooth(keep_at);
+    entity.wish_angvel = WishAngVel::AimSmooth(escorted_translation);
+}
+
+/// Entities with a different owner are hostile. Wrecks are not.
+fn nearest_hostile(
+    sim: &Simulation,
+    entity: &Entity,
+    translation: Vector2<f32>,
+    range: f32,
+) -> Option<EntityId> {
+    let mut nearest = None;
+    let mut nearest_distance_squared = range * range;
+
+    sim.physics
+        .query_pipeline
+        .colliders_with_aabb_intersecting_aabb(
+            &Aabb::from_half_extents(translation.into(), Vector2::new(range, range)),
+            |collider| {
+                let collider = sim.physics.collider(*collider);
+                if collider.user_data.shield() {
+                    return true;
+                }
+
+                let other_id = collider.user_data.entity_id();
+                let hostile = sim
+                    .entities
+                    .get(&other_id)
+                    .is_some_and(|other| !other.wreck && other.owner != entity.owner);
+                if hostile {
+                    let distance_squared =
+                        (collider.position().translation.vector - translation).magnitude_squared();
+                    if distance_squared < nearest_distance_squared {
+                        nearest_distance_squared = distance_squared;
+                        nearest = Some(other_id);
+                    }
+                }
+
+                true
+            },
+        );
+
+    nearest
+}
+
+// ####################################################################################
+// ################################### TEST ###########################################
+// ####################################################################################
+
+#[test]
+fn test_ai_ship_data_verify() {
+    let mut data = AiShipData {
+        engage_range: 20.0,
+        preferred_range: 50.0,
+        flee_hull: 2.0,
+        patrol_radius: -1.0,
+    };
+    data.verify();
+
+    approx::assert_relative_eq!(data.preferred_range, 20.0);
+    approx::assert_relative_eq!(data.flee_hull, 1.0);
+    approx::assert_relative_eq!(data.patrol_radius, 0.0);
+}
+
+#[cfg(test)]
+fn test_spawn_ship(sim: &mut Simulation, owner: u64, x: f32) -> (EntityId, usize) {
+    sim.spawn_entity(
+        EntitySave::new(
+            entity::test_entity_data(serde_json::json!({ "hull": 10.0 })),
+            ClientId::from_u64(owner),
+            Isometry2::translation(x, 0.0),
+            Vector2::zeros(),
+            0.0,
+        ),
+        None,
+        None,
+        None,
+    )
+}
+
+#[cfg(test)]
+fn test_move_ship(sim: &mut Simulation, entity_id: EntityId, x: f32) {
+    let rb = sim.entities[&entity_id].rb;
+    sim.physics
+        .body_mut(rb)
+        .set_translation(vector![x, 0.0], true);
+    sim.physics.step(DT);
+}
+
+#[test]
+fn test_ai_engage_flee() {
+    let (mut sim, _) = test_simulation();
+    let (_, entity_idx) = test_spawn_ship(&mut sim, 1, 0.0);
+    test_spawn_ship(&mut sim, 1, 10.0);
+    let (hostile_id, _) = test_spawn_ship(&mut sim, 2, 20.0);
+    sim.physics.step(DT);
+
+    let mut ai = AiShip::default();
+    ai.update(&mut sim, entity_idx);
+    assert!(matches!(ai.state, AiState::Engage { target } if target == hostile_id));
+    let entity = &sim.entities[entity_idx];
+    assert_eq!(entity.target, Some(hostile_id));
+    assert!(entity.wish_fire);
+
+    // Below flee hull.
+    sim.entities[entity_idx].take_hull(9.0);
+    ai.next_think = 0.0;
+    ai.update(&mut sim, entity_idx);
+    assert!(matches!(ai.state, AiState::Flee { from } if from == hostile_id));
+    assert!(matches!(
+        sim.entities[entity_idx].wish_linvel,
+        WishLinVel::ForceAbsolute(away) if away.x < 0.0
+    ));
+}
+
+#[test]
+fn test_ai_disengage_retaliate() {
+    let (mut sim, _) = test_simulation();
+    let (entity_id, entity_idx) = test_spawn_ship(&mut sim, 1, 0.0);
+    let (hostile_id, _) = test_spawn_ship(&mut sim, 2, 20.0);
+    sim.physics.step(DT);
+
+    let mut ai = AiShip::default();
+    ai.update(&mut sim, entity_idx);
+    assert!(matches!(ai.state, AiState::Engage { .. }));
+
+    // Still within disengage range.
+    let engage_range = AiShipData::default().engage_range;
+    test_move_ship(&mut sim, hostile_id, engage_range * 1.2);
+    ai.next_think = 0.0;
+    ai.update(&mut sim, entity_idx);
+    assert!(matches!(ai.state, AiState::Engage { .. }));
+
+    test_move_ship(
+        &mut sim,
+        hostile_id,
+        engage_range * DISENGAGE_MULTIPLIER + 1.0,
+    );
+    ai.next_think = 0.0;
+    ai.update(&mut sim, entity_idx);
+    assert!(matches!(ai.state, AiState::Task));
+    assert!(!sim.entities[entity_idx].wish_fire);
+
+    // Grazing contacts are ignored.
+    ai.on_event(&SimulationEvent::Damaged {
+        entity_id,
+        by: Some(hostile_id),
+        amount: 0.0,
+    });
+    ai.update(&mut sim, entity_idx);
+    assert!(matches!(ai.state, AiState::Task));
+
+    ai.on_event(&SimulationEvent::Damaged {
+        entity_id,
+        by: Some(hostile_id),
+        amount: 1.0,
+    });
+    ai.update(&mut sim, entity_idx);
+    assert!(matches!(ai.state, AiState::Engage { target } if target == hostile_id));
+}
+
+#[test]
+fn test_ai_patrol() {
+    let (mut sim, _) = test_simulation();
+    let (entity_id, entity_idx) = test_spawn_ship(&mut sim, 1, 0.0);
+    sim.physics.step(DT);
+
+    let data = AiShipData {
+        patrol_radius: 10.0,
+        ..Default::default()
+    };
+    let mut ai = AiShip::new(&data, Vector2::zeros());
+    ai.update(&mut sim, entity_idx);
+    let AiTask::Patrol { waypoint, .. } = ai.task else {
+        panic!("expected patrol task");
+    };
+    assert!(waypoint.magnitude() <= 10.0);
+    assert!(matches!(
+        sim.entities[entity_idx].wish_linvel,
+        WishLinVel::PositionSmooth(position) if position == waypoint
+    ));
+
+    // Orders take precedence and loop through waypoints.
+    let waypoints = [vector![20.0, 0.0], vector![0.0, 20.0]];
+    sim.entities[entity_idx].orders.push_back(Order::Patrol {
+        waypoints: waypoints.into_iter().collect(),
+        next: 0,
+    });
+    ai.update(&mut sim, entity_idx);
+    assert!(matches!(
+        sim.entities[entity_idx].wish_linvel,
+        WishLinVel::PositionSmooth(position) if position == waypoints[0]
+    ));
+
+    test_move_ship(&mut sim, entity_id, 20.0);
+    ai.update(&mut sim, entity_idx);
+    ai.update(&mut sim, entity_idx);
+    assert!(matches!(
+        sim.entities[entity_idx].orders.front(),
+        Some(Order::Patrol { next: 1, .. })
+    ));
+    assert!(matches!(
+        sim.entities[entity_idx].wish_linvel,
+        WishLinVel::PositionSmooth(position) if position == waypoints[1]
+    ));
+}
+
+#[test]
+fn test_ai_escort_idle() {
+    let (mut sim, _) = test_simulation();
+    let (_, entity_idx) = test_spawn_ship(&mut sim, 1, 0.0);
+    let (escorted_id, _) = test_spawn_ship(&mut sim, 1, 20.0);
+    sim.physics.step(DT);
+
+    let mut ai = AiShip::with_task(AiTask::Escort {
+        entity_id: escorted_id,
+    });
+    ai.update(&mut sim, entity_idx);
+    assert!(matches!(
+        sim.entities[entity_idx].wish_linvel,
+        WishLinVel::PositionSmooth(position) if position == vector![20.0 - ESCORT_DISTANCE, 0.0]
+    ));
+
+    sim.despawn_entity(escorted_id, None);
+    ai.update(&mut sim, entity_idx);
+    assert!(matches!(ai.task, AiTask::Idle));
+    ai.update(&mut sim, entity_idx);
+    assert!(matches!(
+        sim.entities[entity_idx].wish_linvel,
+        WishLinVel::Cancel
+    ));
+}
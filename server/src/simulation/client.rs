@@ -3,31 +3,101 @@ use std::collections::VecDeque;
 
 /// How long to keep an entity in known entities after it stop being seen.
 const KNOWNS_ENTITY_TIMEOUT: f64 = 12.0;
+/// Limits for [ClientInbound::SetView].
+const MAX_VIEW_RADIUS: f32 = 64.0;
+const MAX_VIEW_DISTANCE: f32 = 200.0;
+/// Quantization steps per unit for translations and velocities.
+const QUANTIZATION: f32 = 32.0;
+/// Unacknowledged snapshots kept to be used as baseline.
+const MAX_SNAPSHOT_HISTORY: usize = 32;
+/// Entities further than this fraction of the view radius are sent less often.
+const PRIORITY_NEAR: f32 = 0.5;
+/// Seconds between states of entities which are not near.
+const PRIORITY_FAR_INTERVAL: f64 = 0.2;
 
 // TODO: Add knows data
 pub struct Client {
     connection: Connection,
+    /// Random id kept when moving between simulations.
+    /// Used to tell apart multiple logins of the same client.
+    pub session_id: u64,
 
     pub view_translation: Vector2<f32>,
     pub view_radius: f32,
 
+    /// Always owned by this client.
+    pub controlled: Option<EntityId>,
+
     entity_id_allocator: NetworkIdAllocator,
     known_entities: AHashMap<EntityId, KnownEntity>,
+
+    next_snapshot_tick: u32,
+    /// Last snapshot acknowledged by the client.
+    baseline: Option<Snapshot>,
+    /// Sent snapshots newer than the baseline.
+    snapshots: VecDeque<Snapshot>,
+
+    /// Connection's sent bytes last time it was taken.
+    reported_sent_bytes: u64,
 }
 impl Client {
     pub fn new(connection: Connection) -> Self {
         Self {
             connection,
+            session_id: rand::random(),
             view_translation: Vector2::new(0.0, 0.0),
             view_radius: 20.0,
+            controlled: None,
             entity_id_allocator: Default::default(),
             known_entities: Default::default(),
+            next_snapshot_tick: 0,
+            baseline: None,
+            snapshots: Default::default(),
+            reported_sent_bytes: 0,
         }
     }
 
+    /// If the entity was seen by this client and not yet removed.
+    pub fn knows(&self, entity_id: EntityId) -> bool {
+        self.known_entities.contains_key(&entity_id)
+    }
+
+    /// Bytes sent since last call.
+    pub fn take_sent_bytes(&mut self) -> u64 {
+        let sent_bytes = self.connection.outbound.sent_bytes();
+        let delta = sent_bytes - self.reported_sent_bytes;
+        self.reported_sent_bytes = sent_bytes;
+        delta
+    }
+
     pub fn clear(&mut self) {
+        self.controlled = None;
         self.entity_id_allocator = Default::default();
         self.known_entities.clear();
+        self.baseline = None;
+        self.snapshots.clear();
+    }
+
+    /// Only affects update priority and the origin of states.
+    /// What the client can see depends on detection.
+    pub fn set_view(&mut self, translation: Vector2<f32>, radius: f32) {
+        if translation.iter().all(|v| v.is_finite()) {
+            self.view_translation = translation.cap_magnitude(MAX_VIEW_DISTANCE);
+        }
+        if radius.is_finite() {
+            self.view_radius = radius.clamp(1.0, MAX_VIEW_RADIUS);
+        }
+    }
+
+    /// Snapshot becomes the new baseline for delta encoding.
+    pub fn ack_state(&mut self, tick: u32) {
+        if let Some(i) = self
+            .snapshots
+            .iter()
+            .position(|snapshot| snapshot.tick == tick)
+        {
+            self.baseline = self.snapshots.drain(..=i).next_back();
+        }
     }
 
     pub fn queue(&self, packet: ClientOutbound) {
@@ -55,57 +125,55 @@ struct KnownEntity {
     was_seen: bool,
 }
 
-/// Returns if the client should be retained.
-pub fn update_client(sim: &mut Simulation, client_idx: usize) {
+pub fn update_client(sim: &mut Simulation, client_idx: usize, detectors: &[Detector]) {
     let client = &mut sim.clients[client_idx];
 
     // Update what client can see.
-    // TODO: detection
-    sim.physics
-        .query_pipeline
-        .colliders_with_aabb_intersecting_aabb(
-            &Aabb::from_half_extents(
-                client.view_translation.into(),
-                Vector2::new(client.view_radius, client.view_radius),
-            ),
-            |collider| {
-                let collider = sim.physics.collider(*collider);
-
-                let entity_id = collider.user_data.entity_id();
-
-                let entity = client.known_entities.entry(entity_id).or_insert_with(|| {
-                    let network_id = client.entity_id_allocator.next();
-
-                    // Notify client of new entity.
-                    client.connection.queue(ClientOutbound::AddEntity {
-                        entity_id,
-                        network_id,
-                        entity_data_id: sim.entities[&entity_id].data,
-                    });
-
-                    KnownEntity {
-                        network_id,
-                        distance_squared: 0.0,
-                        last_seen: 0.0,
-                        last_sent: 0.0,
-                        was_seen: true,
-                    }
-                });
+    detect(&sim.physics, &sim.entities, detectors, |collider| {
+        let entity_id = collider.user_data.entity_id();
+
+        let entity = client.known_entities.entry(entity_id).or_insert_with(|| {
+            let network_id = client.entity_id_allocator.next();
+
+            // Notify client of new entity.
+            let entity = &sim.entities[&entity_id];
+            client.connection.queue(ClientOutbound::AddEntity {
+                entity_id,
+                network_id,
+                entity_data_id: entity.data,
+                wreck: entity.wreck,
+            });
 
-                entity.distance_squared = (collider.position().translation.vector
-                    - client.view_translation)
-                    .magnitude_squared();
-                entity.last_seen = sim.sim_time;
+            KnownEntity {
+                network_id,
+                distance_squared: 0.0,
+                last_seen: 0.0,
+                last_sent: 0.0,
+                was_seen: true,
+            }
+        });
 
-                true
-            },
-        );
+        entity.distance_squared =
+            (collider.position().translation.vector - client.view_translation).magnitude_squared();
+        entity.last_seen = sim.sim_time;
+    });
 
     // Send state to clients.
     let time = sim.sim_time;
-    let origin = client.view_translation;
+    let origin = quantize_translation(client.view_translation);
+    let near_squared = (client.view_radius * PRIORITY_NEAR).powi(2);
+    let baseline = client.baseline.as_ref();
+    let mut snapshot = Snapshot {
+        tick: client.next_snapshot_tick,
+        entities: Default::default(),
+    };
+    client.next_snapshot_tick = client.next_snapshot_tick.wrapping_add(1);
     let mut entitie_states = Vec::new();
+    let mut freed_network_ids = Vec::new();
     client.known_entities.retain(|entity_id, known_entity| {
+        let baseline_state =
+            baseline.and_then(|baseline| baseline.entities.get(&known_entity.network_id));
+
         if known_entity.last_seen == time {
             if !known_entity.was_seen {
                 known_entity.was_seen = true;
@@ -114,20 +182,43 @@ pub fn update_client(sim: &mut Simulation, client_idx: usize) {
                 });
             }
 
-            // TODO: Send entities that are far away or not changing less often.
+            // Far entities are sent less often. Client extrapolates with velocity.
+            if let Some(baseline_state) = baseline_state {
+                if known_entity.distance_squared > near_squared
+                    && time - known_entity.last_sent < PRIORITY_FAR_INTERVAL
+                {
+                    snapshot
+                        .entities
+                        .insert(known_entity.network_id, *baseline_state);
+                    return true;
+                }
+            }
 
-            let rb = sim.physics.body(sim.entities[entity_id].rb);
-            let translation = *rb.translation();
-            let rotation =
-                rb.rotation()
-                    .angle()
-                    .mul_add(u16::MAX as f32 / TAU, u16::MAX as f32 * 0.5) as u16;
+            let entity = &sim.entities[entity_id];
+            let rb = sim.physics.body(entity.rb);
 
-            entitie_states.push(EntityState {
-                network_id: known_entity.network_id,
-                relative_translation: translation,
-                rotation,
-            });
+            let shield = entity
+                .shield
+                .as_ref()
+                .zip(entity.data.shield.as_ref())
+                .filter(|(shield, _)| shield.up)
+                .map(|(shield, shield_data)| {
+                    (shield.flux_fraction(shield_data) * u8::MAX as f32) as u8
+                });
+
+            let state = QuantizedState {
+                translation: clamp_translation(quantize_translation(*rb.translation()), origin),
+                velocity: quantize_velocity(*rb.linvel()),
+                rotation: quantize_rotation(rb.rotation().angle()),
+                shield,
+            };
+
+            let entity_state =
+                EntityState::delta(known_entity.network_id, &state, baseline_state, origin);
+            if entity_state.changed() {
+                entitie_states.push(entity_state);
+            }
+            snapshot.entities.insert(known_entity.network_id, state);
 
             known_entity.last_sent = time;
 
@@ -140,7 +231,7 @@ pub fn update_client(sim: &mut Simulation, client_idx: usize) {
                     network_id: known_entity.network_id,
                 });
 
-                client.entity_id_allocator.free(known_entity.network_id);
+                freed_network_ids.push(known_entity.network_id);
 
                 false
             } else {
@@ -151,17 +242,64 @@ pub fn update_client(sim: &mut Simulation, client_idx: usize) {
                     });
                 }
 
+                // Keep what the client has in case it is seen again.
+                if let Some(baseline_state) = baseline_state {
+                    snapshot
+                        .entities
+                        .insert(known_entity.network_id, *baseline_state);
+                }
+
                 true
             }
         }
     });
 
+    // A reused id must not be delta encoded against the removed entity.
+    for network_id in freed_network_ids {
+        if let Some(baseline) = &mut client.baseline {
+            baseline.entities.remove(&network_id);
+        }
+        for snapshot in &mut client.snapshots {
+            snapshot.entities.remove(&network_id);
+        }
+        client.entity_id_allocator.free(network_id);
+    }
+
     client.queue(ClientOutbound::State {
+        tick: snapshot.tick,
+        baseline_tick: client.baseline.as_ref().map(|baseline| baseline.tick),
         time,
         origin,
         entitie_states,
     });
 
+    if client.snapshots.len() >= MAX_SNAPSHOT_HISTORY {
+        client.snapshots.pop_front();
+    }
+    client.snapshots.push_back(snapshot);
+
+    // Same origin as the state.
+    let origin = Vector2::new(origin[0] as f32, origin[1] as f32) / QUANTIZATION;
+
+    // Send new projectiles.
+    let projectiles = sim
+        .new_projectiles
+        .iter()
+        .filter(|projectile| {
+            detectors
+                .iter()
+                .any(|detector| detector.detects(projectile.translation, 1.0))
+        })
+        .map(|projectile| ProjectileSpawn {
+            weapon_data_id: projectile.weapon,
+            relative_translation: projectile.translation - origin,
+            velocity: projectile.velocity,
+        })
+        .collect::<Vec<_>>();
+    if !projectiles.is_empty() {
+        client.queue(ClientOutbound::SpawnProjectiles { projectiles });
+    }
+
     client.flush();
 }
 
@@ -189,12 +327,110 @@ impl NetworkIdAllocator {
     }
 }
 
+/// What the client has after applying a state.
+struct Snapshot {
+    tick: u32,
+    entities: AHashMap<u32, QuantizedState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct QuantizedState {
+    /// Absolute. See [quantize_translation].
+    translation: [i32; 2],
+    velocity: [i16; 2],
+    rotation: u16,
+    /// Some flux `[0..1]` when the shield is up.
+    shield: Option<u8>,
+}
+
+fn quantize_translation(translation: Vector2<f32>) -> [i32; 2] {
+    [
+        (translation.x * QUANTIZATION).round() as i32,
+        (translation.y * QUANTIZATION).round() as i32,
+    ]
+}
+
+/// What the client gets from a translation relative to `origin`.
+/// Further entities stay at the edge until they come back in range.
+fn clamp_translation(translation: [i32; 2], origin: [i32; 2]) -> [i32; 2] {
+    [0, 1].map(|i| {
+        translation[i].clamp(
+            origin[i].saturating_add(i16::MIN as i32),
+            origin[i].saturating_add(i16::MAX as i32),
+        )
+    })
+}
+
+fn quantize_velocity(velocity: Vector2<f32>) -> [i16; 2] {
+    [
+        (velocity.x * QUANTIZATION).round() as i16,
+        (velocity.y * QUANTIZATION).round() as i16,
+    ]
+}
+
+/// `[-PI, PI]` to `[0, u16::MAX]`.
+fn quantize_rotation(angle: f32) -> u16 {
+    angle.mul_add(u16::MAX as f32 / TAU, u16::MAX as f32 * 0.5) as u16
+}
+
+/// Only the fields which changed from the baseline are some.
+/// Fields missing from the baseline are always sent.
 #[derive(Serialize)]
 pub struct EntityState {
     network_id: u32,
-    // TODO: Pack tranlation into 2 i16
+    /// Quantized relative to the state's origin.
+    relative_translation: Option<[i16; 2]>,
+    /// Quantized units per second.
+    velocity: Option<[i16; 2]>,
+    rotation: Option<u16>,
+    /// Some flux `[0..1]` when the shield is up.
+    shield: Option<Option<u8>>,
+}
+impl EntityState {
+    fn delta(
+        network_id: u32,
+        state: &QuantizedState,
+        baseline: Option<&QuantizedState>,
+        origin: [i32; 2],
+    ) -> Self {
+        fn changed<T: PartialEq + Copy>(
+            baseline: Option<&QuantizedState>,
+            field: impl Fn(&QuantizedState) -> T,
+            current: T,
+        ) -> Option<T> {
+            (baseline.map(field) != Some(current)).then_some(current)
+        }
+
+        Self {
+            network_id,
+            relative_translation: changed(baseline, |s| s.translation, state.translation).map(
+                |translation| {
+                    [
+                        (translation[0] - origin[0]).clamp(i16::MIN as i32, i16::MAX as i32) as i16,
+                        (translation[1] - origin[1]).clamp(i16::MIN as i32, i16::MAX as i32) as i16,
+                    ]
+                },
+            ),
+            velocity: changed(baseline, |s| s.velocity, state.velocity),
+            rotation: changed(baseline, |s| s.rotation, state.rotation),
+            shield: changed(baseline, |s| s.shield, state.shield),
+        }
+    }
+
+    fn changed(&self) -> bool {
+        self.relative_translation.is_some()
+            || self.velocity.is_some()
+            || self.rotation.is_some()
+            || self.shield.is_some()
+    }
+}
+
+/// Client extrapolate projectiles from their spawn.
+#[derive(Serialize)]
+pub struct ProjectileSpawn {
+    weapon_data_id: WeaponDataId,
     relative_translation: Vector2<f32>,
-    rotation: u16,
+    velocity: Vector2<f32>,
 }
 
 #[derive(Serialize)]
@@ -203,15 +439,24 @@ pub enum ClientOutbound {
         client_id: ClientId,
         system_id: SimulationId,
     },
+    /// Should be acknowledged with [ClientInbound::AckState].
+    ///
+    /// Entities not in `entitie_states` are unchanged from the baseline.
+    /// Without baseline, every seen entity is included.
     State {
+        tick: u32,
+        baseline_tick: Option<u32>,
         time: f64,
-        origin: Vector2<f32>,
+        /// Translations are quantized relative to this.
+        origin: [i32; 2],
         entitie_states: Vec<EntityState>,
     },
     AddEntity {
         entity_id: EntityId,
         network_id: u32,
         entity_data_id: EntityDataId,
+        /// Same shape as the data, but does nothing.
+        wreck: bool,
     },
     RemoveEntity {
         network_id: u32,
@@ -225,6 +470,50 @@ pub enum ClientOutbound {
     ClientShips {
         ships: Vec<u8>,
     },
+    OnlineCount {
+        total: u32,
+        simulation: u32,
+    },
+    /// Response to control request.
+    /// Also sent when control is lost.
+    ControlledEntity {
+        entity_id: Option<EntityId>,
+    },
+    /// A ship owned by this client left for another simulation.
+    ShipTraveled {
+        entity_id: EntityId,
+        simulation_id: SimulationId,
+    },
+    /// Can be used to login to any instance until it expires or is revoked.
+    SessionToken {
+        token: Vec<u8>,
+    },
+    /// Reconnect to this instance to enter the simulation.
+    /// Session token can be used to login.
+    /// Connection will be closed.
+    Redirect {
+        addr: String,
+        simulation_id: SimulationId,
+    },
+    /// An entity this client knows or a ship was destroyed.
+    /// Entity is removed separately.
+    EntityDied {
+        entity_id: EntityId,
+        translation: Vector2<f32>,
+        owner: Option<ClientId>,
+        killer: Option<EntityId>,
+        killer_owner: Option<ClientId>,
+    },
+    /// Orders of an owned entity.
+    /// Sent when they change and when entering the simulation.
+    Orders {
+        entity_id: EntityId,
+        orders: Vec<Order>,
+    },
+    /// Use the same origin as the last state.
+    SpawnProjectiles {
+        projectiles: Vec<ProjectileSpawn>,
+    },
 }
 impl Packet for ClientOutbound {
     fn serialize(self) -> Vec<u8> {
@@ -235,6 +524,11 @@ impl Packet for ClientOutbound {
     fn parse(_buf: Vec<u8>) -> anyhow::Result<Self> {
         unimplemented!()
     }
+
+    /// Only the latest state matters.
+    fn replaceable(&self) -> bool {
+        matches!(self, Self::State { .. })
+    }
 }
 
 /// Do not use id directly. Instead use int and use try from.
@@ -245,6 +539,47 @@ pub enum ClientInbound {
         radius: f32,
     },
     CreateFirstShip,
+    /// Only owned entity can be controlled.
+    TakeControl {
+        entity_id: u64,
+    },
+    ReleaseControl,
+    /// Send an owned ship to another simulation.
+    /// Clients controlling the ship will follow it.
+    TravelShip {
+        entity_id: u64,
+        simulation_id: u32,
+    },
+    /// Move to another simulation without reconnecting.
+    /// Client will be redirected if the simulation is on another instance.
+    SwitchSimulation {
+        simulation_id: u32,
+    },
+    /// Latest [ClientOutbound::State] received.
+    AckState {
+        tick: u32,
+    },
+    /// Will respond with [ClientOutbound::OnlineCount].
+    QueryOnlineCount,
+    /// Invalidate every session token issued so far.
+    /// Connected sessions are not closed.
+    RevokeSessionTokens,
+    /// Orders for an owned entity. Replace current orders unless appending.
+    /// Invalid orders are ignored.
+    /// Will respond with [ClientOutbound::Orders].
+    SetOrders {
+        entity_id: u64,
+        orders: Vec<ClientOrder>,
+        append: bool,
+    },
+    /// Applied to the controlled entity until the next input.
+    ControlInput {
+        wish_linvel: WishLinVel,
+        wish_angvel: WishAngVel,
+        wish_aim: WishAim,
+        wish_fire: bool,
+        wish_shield: bool,
+    },
 }
 impl Packet for ClientInbound {
     fn serialize(self) -> Vec<u8> {
@@ -255,3 +590,68 @@ impl Packet for ClientInbound {
         bin_decode(&buf)
     }
 }
+
+// ####################################################################################
+// ############## TEST ################################################################
+// ####################################################################################
+
+#[test]
+fn test_entity_state_delta() {
+    let state = QuantizedState {
+        translation: quantize_translation(Vector2::new(10.0, -5.0)),
+        velocity: quantize_velocity(Vector2::new(1.0, 0.0)),
+        rotation: quantize_rotation(0.5),
+        shield: None,
+    };
+    let origin = quantize_translation(Vector2::new(9.0, -5.0));
+
+    // Without baseline everything is sent.
+    let full = EntityState::delta(0, &state, None, origin);
+    assert_eq!(full.relative_translation, Some([QUANTIZATION as i16, 0]));
+    assert!(full.velocity.is_some() && full.rotation.is_some() && full.shield.is_some());
+
+    assert!(!EntityState::delta(0, &state, Some(&state), origin).changed());
+
+    let moved = QuantizedState {
+        translation: quantize_translation(Vector2::new(10.5, -5.0)),
+        ..state
+    };
+    let delta = EntityState::delta(0, &moved, Some(&state), origin);
+    assert_eq!(
+        delta.relative_translation,
+        Some([(QUANTIZATION * 1.5) as i16, 0])
+    );
+    assert!(delta.velocity.is_none() && delta.rotation.is_none() && delta.shield.is_none());
+}
+
+#[test]
+fn test_far_entity_state() {
+    let far = QuantizedState {
+        translation: quantize_translation(Vector2::new(2000.0, 0.0)),
+        velocity: [0, 0],
+        rotation: 0,
+        shield: None,
+    };
+
+    // Out of range. The client gets it at the edge.
+    let origin = quantize_translation(Vector2::zeros());
+    let sent = QuantizedState {
+        translation: clamp_translation(far.translation, origin),
+        ..far
+    };
+    let delta = EntityState::delta(0, &sent, None, origin);
+    assert_eq!(delta.relative_translation, Some([i16::MAX, 0]));
+
+    // Back in range without moving. Corrected against what the client has.
+    let origin = quantize_translation(Vector2::new(1500.0, 0.0));
+    let state = QuantizedState {
+        translation: clamp_translation(far.translation, origin),
+        ..far
+    };
+    assert_eq!(state.translation, far.translation);
+    let delta = EntityState::delta(0, &state, Some(&sent), origin);
+    assert_eq!(
+        delta.relative_translation,
+        Some([(500.0 * QUANTIZATION) as i16, 0])
+    );
+}
@@ -0,0 +1,96 @@
+use super::*;
+
+/// Entity signatures are clamped to this.
+pub const MAX_SIGNATURE: f32 = 4.0;
+
+/// Clients only see what their owned entities detect.
+#[derive(Debug, Clone, Copy)]
+pub struct Detector {
+    pub translation: Vector2<f32>,
+    pub range: f32,
+}
+impl Detector {
+    /// A signature of 1 is detected at range.
+    pub fn detects(&self, translation: Vector2<f32>, signature: f32) -> bool {
+        (translation - self.translation).magnitude_squared() <= (self.range * signature).powi(2)
+    }
+}
+
+/// Detectors of every client with owned entities in this simulation.
+pub fn client_detectors(sim: &Simulation) -> AHashMap<ClientId, SmallVec<[Detector; 4]>> {
+    let mut detectors: AHashMap<ClientId, SmallVec<[Detector; 4]>> = AHashMap::new();
+
+    for entity in sim.entities.values() {
+        let Some(owner) = entity.owner else {
+            continue;
+        };
+        if !sim.clients.contains_key(&owner) {
+            continue;
+        }
+
+        detectors.entry(owner).or_default().push(Detector {
+            translation: *sim.physics.body(entity.rb).translation(),
+            range: entity.data.detector_range,
+        });
+    }
+
+    detectors
+}
+
+/// Called for every detected collider.
+/// Can be called more than once for the same entity.
+pub fn detect(
+    physics: &Physics,
+    entities: &IndexMap<EntityId, Entity, RandomState>,
+    detectors: &[Detector],
+    mut on_detected: impl FnMut(&Collider),
+) {
+    for detector in detectors {
+        let half_extent = detector.range * MAX_SIGNATURE;
+
+        physics
+            .query_pipeline
+            .colliders_with_aabb_intersecting_aabb(
+                &Aabb::from_half_extents(
+                    detector.translation.into(),
+                    Vector2::new(half_extent, half_extent),
+                ),
+                |collider| {
+                    let collider = physics.collider(*collider);
+
+                    let detected =
+                        entities
+                            .get(&collider.user_data.entity_id())
+                            .is_some_and(|entity| {
+                                detector.detects(
+                                    collider.position().translation.vector,
+                                    entity.data.signature,
+                                )
+                            });
+                    if detected {
+                        on_detected(collider);
+                    }
+
+                    true
+                },
+            );
+    }
+}
+
+// ####################################################################################
+// ################################### TEST ###########################################
+// ####################################################################################
+
+#[test]
+fn test_detects() {
+    let detector = Detector {
+        translation: Vector2::new(1.0, 0.0),
+        range: 10.0,
+    };
+
+    assert!(detector.detects(Vector2::new(11.0, 0.0), 1.0));
+    assert!(!detector.detects(Vector2::new(11.1, 0.0), 1.0));
+    // Bigger signature is detected further.
+    assert!(detector.detects(Vector2::new(1.0, -15.0), 2.0));
+    assert!(!detector.detects(Vector2::new(1.0, -15.0), 0.5));
+}
@@ -5,13 +5,26 @@ use super::*;
 /// Minimum 3x3.
 type ArmorCells = SmallVec<[u8; 16]>;
 
-// TODO: Events (hit, leaving, death, etc)
+/// Size of an armor cell in world unit.
+/// Should be the same as client's `Util.ARMOR_CELLS_SIZE`.
+pub const ARMOR_CELLS_SIZE: f32 = 1.0 / 14.0;
+
+/// How damage is spread around the hit armor cell.
+const ARMOR_CELLS_DAMAGE_KERNEL: [[f32; 3]; 3] = [
+    [1.0, 2.0, 1.0], //
+    [2.0, 4.0, 2.0],
+    [1.0, 2.0, 1.0],
+];
+
+/// Damage per unit of contact impulse.
+const CONTACT_DAMAGE_MULTIPLIER: f32 = 1.0;
+
 /// A ship, drone, missile or debris.
 ///
 /// May only have one shield.
 pub struct Entity {
     pub data: EntityDataId,
-    owner: Option<ClientId>,
+    pub owner: Option<ClientId>,
 
     pub rb: RigidBodyHandle,
 
@@ -21,17 +34,39 @@ pub struct Entity {
     armor_max: f32,
     armor_cells: ArmorCells,
 
+    /// Derived from data and stat modifiers. See [Entity::compute_stats].
     linear_acceleration: f32,
     angular_acceleration: f32,
     max_linear_velocity: f32,
     max_angular_velocity: f32,
+    hull_regen: f32,
+
+    /// One for each of the data's weapon slots.
+    pub turrets: SmallVec<[Turret; 4]>,
+
+    /// Some if the data has a shield.
+    pub shield: Option<Shield>,
 
     pub wish_angvel: WishAngVel,
     pub wish_linvel: WishLinVel,
-    // pub wish_aim: (),
+    pub wish_aim: WishAim,
+    /// Fire turrets which are ready and on target.
+    pub wish_fire: bool,
+    pub wish_shield: bool,
     pub controlled: bool,
 
+    /// Take hull from this wreck when in range. See [salvage].
+    pub wish_salvage: Option<EntityId>,
+
     pub target: Option<EntityId>,
+    /// Executed by ai when not controlled.
+    pub orders: Orders,
+    /// Credited for the kill.
+    pub last_hit_by: Option<EntityId>,
+
+    /// Remains of a destroyed entity.
+    /// Has no owner, does not act and decays over time.
+    pub wreck: bool,
 
     modifiers: SmallVec<[Modifier; 4]>,
 }
@@ -54,6 +89,9 @@ impl Entity {
             entity_id,
             group_ignore,
         );
+        if save.wreck {
+            simulation.physics.set_groups(rb, group::GROUPS_DEBRIS);
+        }
 
         let mut s = Self {
             data: save.data,
@@ -67,37 +105,94 @@ impl Entity {
             angular_acceleration: save.data.angular_acceleration,
             max_linear_velocity: save.data.max_linear_velocity,
             max_angular_velocity: save.data.max_angular_velocity,
+            hull_regen: save.data.hull_regen,
+            turrets: save.turrets.into(),
+            shield: save
+                .data
+                .shield
+                .as_ref()
+                .filter(|_| !save.wreck)
+                .map(|shield_data| {
+                    Shield::new(
+                        &mut simulation.physics,
+                        rb,
+                        entity_id,
+                        shield_data,
+                        save.data.groups.filter,
+                        save.shield,
+                    )
+                }),
             wish_angvel: WishAngVel::None,
             wish_linvel: WishLinVel::None,
+            wish_aim: WishAim::Rest,
+            wish_fire: false,
+            wish_shield: save.shield.wish_up,
             controlled: false,
+            wish_salvage: None,
             target,
+            orders: save.orders.into(),
+            last_hit_by: None,
+            wreck: save.wreck,
             modifiers: SmallVec::new(),
         };
 
-        for new_event in save.data.on_new.iter() {
-            match new_event {
-                EntityEvent::AddAiShip => {
-                    s.modifiers.push(Modifier::AiShip);
+        for modifier_save in save.modifier_saves {
+            match modifier_save {
+                ModifierSave::AiShip { task } => {
+                    s.modifiers.push(Modifier::AiShip(AiShip::with_task(task)));
                 }
-                EntityEvent::AddAiSeek => {
+                ModifierSave::AiSeek => {
                     s.wish_linvel = WishLinVel::ForceRelative(Vector2::new(1.0, 0.0));
                     s.modifiers.push(Modifier::AiSeek);
                 }
+                ModifierSave::Stat(modifier) => {
+                    s.modifiers.push(Modifier::Stat(modifier.sanitize()));
+                }
+            }
+        }
+
+        // Already applied before being saved.
+        if !save.spawned {
+            for new_event in save.data.on_new.iter() {
+                match new_event {
+                    EntityEvent::AddAiShip => {
+                        s.modifiers.push(Modifier::AiShip(AiShip::new(
+                            &save.data.ai,
+                            save.position.translation.vector,
+                        )));
+                    }
+                    EntityEvent::AddAiSeek => {
+                        s.wish_linvel = WishLinVel::ForceRelative(Vector2::new(1.0, 0.0));
+                        s.modifiers.push(Modifier::AiSeek);
+                    }
+                    EntityEvent::ApplyStatModifier(modifier) => {
+                        s.add_stat_modifier(*modifier);
+                    }
+                }
             }
         }
 
+        s.compute_stats();
+
         s
     }
 
     pub fn save(&self, sim: &Simulation) -> EntitySave {
         let body = sim.physics.body(self.rb);
 
-        let mut modifier_saves = SmallVec::new();
+        let mut modifier_saves = Vec::new();
         for modifier in self.modifiers.iter() {
             match modifier {
                 Modifier::Nothing => {}
-                Modifier::AiShip => {}
-                Modifier::AiSeek => {}
+                Modifier::AiShip(ai) => {
+                    modifier_saves.push(ModifierSave::AiShip { task: ai.task });
+                }
+                Modifier::AiSeek => {
+                    modifier_saves.push(ModifierSave::AiSeek);
+                }
+                Modifier::Stat(modifier) => {
+                    modifier_saves.push(ModifierSave::Stat(*modifier));
+                }
             }
         }
 
@@ -109,9 +204,241 @@ impl Entity {
             angvel: body.angvel(),
             hull: self.hull,
             armor_cells: self.armor_cells.clone(),
+            turrets: self.turrets.to_vec(),
+            shield: self
+                .shield
+                .as_ref()
+                .map(|shield| shield.save(self.wish_shield))
+                .unwrap_or_default(),
+            orders: self.orders.iter().cloned().collect(),
             modifier_saves,
+            spawned: true,
+            wreck: self.wreck,
         }
     }
+
+    /// Follows the modifier's stacking rule.
+    pub fn add_stat_modifier(&mut self, modifier: StatModifier) {
+        let modifier = modifier.sanitize();
+
+        let existing = self
+            .modifiers
+            .iter_mut()
+            .find_map(|existing| match existing {
+                Modifier::Stat(existing)
+                    if existing.source == modifier.source && existing.stat == modifier.stat =>
+                {
+                    Some(existing)
+                }
+                _ => None,
+            });
+        match (modifier.stacking, existing) {
+            (Stacking::Refresh, Some(existing)) => *existing = modifier,
+            (Stacking::Ignore, Some(_)) => return,
+            _ => {
+                let num_stat_modifiers = self
+                    .modifiers
+                    .iter()
+                    .filter(|modifier| matches!(modifier, Modifier::Stat(_)))
+                    .count();
+                if num_stat_modifiers >= MAX_STAT_MODIFIERS {
+                    return;
+                }
+                self.modifiers.push(Modifier::Stat(modifier));
+            }
+        }
+
+        self.compute_stats();
+    }
+
+    /// Apply stat modifiers to data's base stats.
+    fn compute_stats(&mut self) {
+        let data = self.data;
+        let stat = |stat, base| {
+            modified_stat(
+                stat,
+                base,
+                self.modifiers.iter().filter_map(|modifier| match modifier {
+                    Modifier::Stat(modifier) => Some(modifier),
+                    _ => None,
+                }),
+            )
+        };
+
+        self.linear_acceleration = stat(Stat::LinearAcceleration, data.linear_acceleration);
+        self.angular_acceleration = stat(Stat::AngularAcceleration, data.angular_acceleration);
+        self.max_linear_velocity = stat(Stat::MaxLinearVelocity, data.max_linear_velocity);
+        self.max_angular_velocity = stat(Stat::MaxAngularVelocity, data.max_angular_velocity);
+        self.armor_max = stat(Stat::ArmorMax, data.armor_max);
+        self.hull_regen = if self.wreck {
+            -wreck_decay_rate(&data)
+        } else {
+            stat(Stat::HullRegen, data.hull_regen)
+        };
+    }
+
+    /// Orders past [MAX_ORDERS] are dropped.
+    /// Entity will get an ai if it does not have one.
+    pub fn set_orders(&mut self, orders: impl Iterator<Item = Order>, append: bool) {
+        if !append {
+            self.orders.clear();
+        }
+        self.orders.extend(orders);
+        self.orders.truncate(MAX_ORDERS);
+
+        if !self.orders.is_empty()
+            && !self
+                .modifiers
+                .iter()
+                .any(|modifier| matches!(modifier, Modifier::AiShip(_)))
+        {
+            self.modifiers.push(Modifier::AiShip(Default::default()));
+        }
+    }
+
+    /// `[0..1]`
+    pub fn hull_ratio(&self) -> f32 {
+        if self.hull_max > 0.0 {
+            self.hull / self.hull_max
+        } else {
+            1.0
+        }
+    }
+
+    /// Stop moving, firing and salvaging.
+    pub fn release_control(&mut self) {
+        self.controlled = false;
+        self.wish_linvel = WishLinVel::Cancel;
+        self.wish_angvel = WishAngVel::Stop;
+        self.wish_aim = WishAim::Rest;
+        self.wish_fire = false;
+        self.wish_salvage = None;
+    }
+
+    /// Remove up to `amount` hull. Returns the removed hull.
+    pub fn take_hull(&mut self, amount: f32) -> f32 {
+        let taken = amount.clamp(0.0, self.hull);
+        self.hull -= taken;
+        taken
+    }
+
+    /// Add hull up to max.
+    pub fn repair(&mut self, amount: f32) {
+        self.hull = (self.hull + amount.max(0.0)).min(self.hull_max);
+    }
+
+    /// Modifiers receive events about this entity.
+    pub fn on_event(&mut self, event: &SimulationEvent) {
+        for modifier in self.modifiers.iter_mut() {
+            match modifier {
+                Modifier::AiShip(ai) => ai.on_event(event),
+                Modifier::Nothing | Modifier::AiSeek | Modifier::Stat(_) => {}
+            }
+        }
+    }
+
+    /// `position` is this entity's body position.
+    ///
+    /// Returns the damage taken, including what shield absorbed.
+    pub fn take_contact_event(
+        &mut self,
+        position: &Isometry2<f32>,
+        event: ContactEvent,
+        dt: f32,
+    ) -> f32 {
+        let local_point = position.inverse_transform_point(&event.point);
+
+        let total = event.force_magnitude * dt * CONTACT_DAMAGE_MULTIPLIER;
+        self.last_hit_by = Some(event.with_entity_id);
+
+        let mut amount = total;
+        if event.shield {
+            amount = self.damage_shield(local_point, amount);
+
+            // Shields pushing each other. Hulls are not touching.
+            if event.with_shield {
+                return total - amount;
+            }
+        }
+
+        if amount > 0.0 {
+            self.damage(local_point, amount);
+        }
+
+        total
+    }
+
+    /// Returns the damage that the shield could not absorb.
+    pub fn damage_shield(&mut self, local_point: Point2<f32>, amount: f32) -> f32 {
+        if let Some((shield, shield_data)) = self.shield.as_mut().zip(self.data.shield.as_ref()) {
+            shield.absorb(shield_data, local_point, amount)
+        } else {
+            amount
+        }
+    }
+
+    /// Damage armor cells around a local space point.
+    /// Damage which armor could not absorb goes to hull.
+    pub fn damage(&mut self, local_point: Point2<f32>, amount: f32) {
+        let cell = ((local_point.coords - self.data.armor_cells_translation) / ARMOR_CELLS_SIZE)
+            .map(|v| v.floor() as i32);
+
+        let overflow = damage_armor_cells(
+            &mut self.armor_cells,
+            self.data.armor_cells_size,
+            self.armor_max,
+            cell,
+            amount,
+        );
+
+        self.hull = (self.hull - overflow).max(0.0);
+    }
+}
+
+/// Spread damage to the armor cells around `center`.
+/// Returns the damage that armor could not absorb.
+fn damage_armor_cells(
+    armor_cells: &mut ArmorCells,
+    size: Vector2<i32>,
+    armor_max: f32,
+    center: Vector2<i32>,
+    amount: f32,
+) -> f32 {
+    // Hit can land slightly outside the grid.
+    let center = Vector2::new(center.x.clamp(0, size.x - 1), center.y.clamp(0, size.y - 1));
+
+    // Only keep kernel cells inside the grid.
+    let mut cells: SmallVec<[(usize, f32); 9]> = SmallVec::new();
+    let mut weight_total = 0.0;
+    for (ky, row) in ARMOR_CELLS_DAMAGE_KERNEL.iter().enumerate() {
+        for (kx, &weight) in row.iter().enumerate() {
+            let x = center.x + kx as i32 - 1;
+            let y = center.y + ky as i32 - 1;
+            if (0..size.x).contains(&x) && (0..size.y).contains(&y) {
+                cells.push(((y * size.x + x) as usize, weight));
+                weight_total += weight;
+            }
+        }
+    }
+
+    let mut overflow = 0.0;
+    for (cell_idx, weight) in cells {
+        let cell_damage = amount * weight / weight_total;
+
+        let Some(cell) = armor_cells.get_mut(cell_idx) else {
+            overflow += cell_damage;
+            continue;
+        };
+
+        let cell_hp = *cell as f32 / u8::MAX as f32 * armor_max;
+        let absorbed = cell_damage.min(cell_hp);
+        overflow += cell_damage - absorbed;
+
+        // Truncate so that small hits still wear armor down.
+        *cell = ((cell_hp - absorbed) / armor_max * u8::MAX as f32) as u8;
+    }
+
+    overflow
 }
 
 /// Returns if the entity should be retained.
@@ -130,6 +457,7 @@ pub fn update_entity_retain(sim: &mut Simulation, entity_idx: usize) -> bool {
     };
 
     // Update modifiers.
+    let mut stats_changed = false;
     let mut modifier_idx = 0;
     while modifier_idx < sim.entities[entity_idx].modifiers.len() {
         let mut modifier = std::mem::take(&mut sim.entities[entity_idx].modifiers[modifier_idx]);
@@ -137,13 +465,24 @@ pub fn update_entity_retain(sim: &mut Simulation, entity_idx: usize) -> bool {
         match &mut modifier {
             Modifier::Nothing => {}
             Modifier::AiSeek => {
-                if let Some(target_idx) = target_idx {
+                // Controlled entity ignore ai.
+                if let (false, Some(target_idx)) = (sim.entities[entity_idx].controlled, target_idx)
+                {
                     let target = *sim.physics.body(sim.entities[target_idx].rb).translation();
                     sim.entities[entity_idx].wish_angvel = WishAngVel::AimSmooth(target);
                 }
             }
-            Modifier::AiShip => {
-                // TODO
+            Modifier::AiShip(ai) => {
+                // Controlled entity ignore ai.
+                if !sim.entities[entity_idx].controlled {
+                    ai.update(sim, entity_idx);
+                }
+            }
+            Modifier::Stat(stat_modifier) => {
+                if stat_modifier.tick(sim.sim_dt) {
+                    modifier = Modifier::Nothing;
+                    stats_changed = true;
+                }
             }
         }
 
@@ -155,7 +494,28 @@ pub fn update_entity_retain(sim: &mut Simulation, entity_idx: usize) -> bool {
         }
     }
 
+    if let Some(wreck_id) = sim.entities[entity_idx].wish_salvage {
+        salvage(sim, entity_idx, wreck_id);
+    }
+
     let entity = &mut sim.entities[entity_idx];
+
+    if stats_changed {
+        entity.compute_stats();
+    }
+    if entity.hull_regen != 0.0 {
+        entity.hull = (entity.hull + entity.hull_regen * sim.sim_dt).min(entity.hull_max);
+    }
+
+    if let Some((shield, shield_data)) = entity.shield.as_mut().zip(entity.data.shield.as_ref()) {
+        shield.update(
+            &mut sim.physics,
+            shield_data,
+            entity.wish_shield,
+            sim.sim_dt,
+        );
+    }
+
     let rb = sim.physics.body_mut(entity.rb);
     let angvel = rb.angvel();
     let linvel = *rb.linvel();
@@ -164,32 +524,13 @@ pub fn update_entity_retain(sim: &mut Simulation, entity_idx: usize) -> bool {
         WishAngVel::None => angvel,
         WishAngVel::Keep => angvel.clamp(-entity.max_angular_velocity, entity.max_angular_velocity),
         WishAngVel::Stop => 0.0,
-        WishAngVel::AimSmooth(aim_to) => {
-            // TODO: May need to rotate this.
-            // aim_to.angle(other)
-            // let offset = Vec2::from_angle(angle).angle_between(to);
-
-            // let offset = angle_to(rotation.0, *aim_to - position.0);
-            // let wish_dir = offset.signum();
-            // let mut close_smooth = offset.abs().min(0.2) / 0.2;
-            // close_smooth *= close_smooth * close_smooth;
-
-            // if wish_dir == angular.velocity.signum() {
-            //     let time_to_target = (offset / angular.velocity).abs();
-            //     let time_to_stop = (angular.velocity / (angular.acceleration)).abs();
-            //     if time_to_target < time_to_stop {
-            //         close_smooth *= -1.0;
-            //     }
-            // }
-
-            // angular.velocity = integrate_angular_velocity(
-            //     angular.velocity,
-            //     wish_dir * angular.max_velocity * close_smooth,
-            //     angular.acceleration,
-            //     time.dt,
-            // );
-            0.0
-        }
+        WishAngVel::AimSmooth(aim_to) => aim_smooth(
+            rb.rotation().angle(),
+            angvel,
+            aim_to - rb.translation(),
+            entity.max_angular_velocity,
+            entity.angular_acceleration,
+        ),
         WishAngVel::Force(force) => force * entity.max_angular_velocity,
     };
 
@@ -219,18 +560,53 @@ pub fn update_entity_retain(sim: &mut Simulation, entity_idx: usize) -> bool {
 
     let wake_up = wish_angvel != angvel || wish_linvel != linvel;
     rb.set_angvel(
-        angvel
-            + (wish_angvel - angvel).clamp(
-                -entity.angular_acceleration * DT,
-                entity.angular_acceleration * DT,
-            ),
+        integrate_angular_velocity(angvel, wish_angvel, entity.angular_acceleration, sim.sim_dt),
         wake_up,
     );
+    // Linear acceleration is per tick, not per second.
     rb.set_linvel(
-        linvel + (wish_linvel - linvel).cap_magnitude(entity.linear_acceleration),
+        integrate_linear_velocity(
+            linvel,
+            wish_linvel,
+            entity.linear_acceleration,
+            sim.sim_dt / DT,
+        ),
         wake_up,
     );
 
+    // Update turrets.
+    let aim = match entity.wish_aim {
+        WishAim::Rest => None,
+        WishAim::Position(position) => {
+            Some(rb.position().inverse_transform_point(&position.into()))
+        }
+    };
+    let group_ignore = rb.user_data.group_ignore();
+    let entity_id = rb.user_data.entity_id();
+    let position = *rb.position();
+    for (turret, slot) in entity
+        .turrets
+        .iter_mut()
+        .zip(entity.data.weapon_slots.iter())
+    {
+        turret.update(slot, aim, sim.sim_dt);
+
+        if entity.wish_fire && turret.on_target {
+            if let Some(weapon) = turret.fire() {
+                let projectile = Projectile::new(
+                    weapon,
+                    position.transform_vector(&slot.translation) + position.translation.vector,
+                    position.rotation.angle() + turret.angle(slot),
+                    linvel,
+                    group_ignore,
+                    entity_id,
+                );
+                sim.projectiles.push(projectile);
+                sim.new_projectiles.push(projectile);
+            }
+        }
+    }
+
     entity.hull > 0.0
 }
 
@@ -249,6 +625,17 @@ pub enum WishAngVel {
     /// **Force should be clamped to 1**
     Force(f32),
 }
+impl WishAngVel {
+    /// Clamp force and replace non-finite values.
+    pub fn sanitize(self) -> Self {
+        match self {
+            Self::AimSmooth(v) if !v.iter().all(|v| v.is_finite()) => Self::None,
+            Self::Force(force) if !force.is_finite() => Self::None,
+            Self::Force(force) => Self::Force(force.clamp(-1.0, 1.0)),
+            other => other,
+        }
+    }
+}
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
 pub enum WishLinVel {
@@ -272,6 +659,42 @@ pub enum WishLinVel {
     /// **Force magnitude should be clamped to 1**
     ForceRelative(Vector2<f32>),
 }
+impl WishLinVel {
+    /// Clamp force and replace non-finite values.
+    pub fn sanitize(self) -> Self {
+        match self {
+            Self::PositionSmooth(v)
+            | Self::PositionOvershoot(v)
+            | Self::ForceAbsolute(v)
+            | Self::ForceRelative(v)
+                if !v.iter().all(|v| v.is_finite()) =>
+            {
+                Self::None
+            }
+            Self::ForceAbsolute(force) => Self::ForceAbsolute(force.cap_magnitude(1.0)),
+            Self::ForceRelative(force) => Self::ForceRelative(force.cap_magnitude(1.0)),
+            other => other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+pub enum WishAim {
+    /// Turrets return to their rest angle.
+    #[default]
+    Rest,
+    /// Turrets aim at a world space position.
+    Position(Vector2<f32>),
+}
+impl WishAim {
+    /// Replace non-finite values.
+    pub fn sanitize(self) -> Self {
+        match self {
+            Self::Position(v) if !v.iter().all(|v| v.is_finite()) => Self::Rest,
+            other => other,
+        }
+    }
+}
 
 /// Something that modify the entity (ai, buff, etc).
 #[derive(Debug, Default)]
@@ -280,7 +703,10 @@ enum Modifier {
     /// Use this to remove a modifier.
     #[default]
     Nothing,
-    AiShip,
+    /// Fight, flee or perform its task. See [AiShip].
+    AiShip(AiShip),
+    /// Removed once expired.
+    Stat(StatModifier),
     /// Will try to face entity's target and go forward at max speed.
     /// If entity has no target just move forward untill a target is set.
     AiSeek,
@@ -290,14 +716,11 @@ enum Modifier {
 // ################################### DATA ###########################################
 // ####################################################################################
 
-// TODO: weapon slot
-// TODO: built-in weapon (take a slot #)
 // TODO: Engine placement
-// TODO: Shields
 pub struct EntityData {
     pub id: u32,
 
-    hull_max: f32,
+    pub hull_max: f32,
 
     armor_max: f32,
     armor_cells_translation: Vector2<f32>,
@@ -314,6 +737,22 @@ pub struct EntityData {
     angular_acceleration: f32,
     max_linear_velocity: f32,
     max_angular_velocity: f32,
+    /// Hull per second.
+    hull_regen: f32,
+
+    pub weapon_slots: Vec<WeaponSlot>,
+    pub shield: Option<ShieldData>,
+
+    /// Entities with a signature of 1 are detected at this range.
+    pub detector_range: f32,
+    /// How easily this entity is detected. See [Detector::detects].
+    pub signature: f32,
+
+    /// Used when the entity has an ai.
+    pub ai: AiShipData,
+
+    /// Spawn a wreck when destroyed.
+    pub leave_wreck: bool,
 
     on_new: Vec<EntityEvent>,
 }
@@ -322,6 +761,7 @@ pub struct EntityData {
 enum EntityEvent {
     AddAiShip,
     AddAiSeek,
+    ApplyStatModifier(StatModifier),
 }
 
 // ####################################################################################
@@ -344,20 +784,53 @@ pub struct EntityDataJson {
     memberships: u32,
     filter: u32,
 
-    // TODO: weapon slot
-    // TODO: built-in weapon (take a slot #)
     // TODO: Engine placement
-    // TODO: Shields
     linear_acceleration: f32,
     angular_acceleration: f32,
     max_linear_velocity: f32,
     max_angular_velocity: f32,
+    #[serde(default)]
+    hull_regen: f32,
+
+    #[serde(default)]
+    weapon_slots: Vec<WeaponSlotJson>,
+    #[serde(default)]
+    shield: Option<ShieldDataJson>,
+
+    #[serde(default)]
+    detector_range: f32,
+    #[serde(default = "EntityDataJson::default_signature")]
+    signature: f32,
+
+    #[serde(default)]
+    ai: AiShipData,
+
+    #[serde(default = "EntityDataJson::default_leave_wreck")]
+    leave_wreck: bool,
 
     on_new: Vec<EntityEvent>,
 }
 impl EntityDataJson {
-    pub fn parse(self, id: u32) -> EntityData {
-        EntityData {
+    fn default_signature() -> f32 {
+        1.0
+    }
+
+    fn default_leave_wreck() -> bool {
+        true
+    }
+
+    pub fn parse(self, id: u32, weapons: &[WeaponData]) -> anyhow::Result<EntityData> {
+        let weapon_slots = self
+            .weapon_slots
+            .into_iter()
+            .enumerate()
+            .map(|(i, slot)| {
+                slot.parse(weapons)
+                    .with_context(|| format!("Invalid weapon slot {}", i))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(EntityData {
             id,
 
             hull_max: self.hull,
@@ -386,9 +859,24 @@ impl EntityDataJson {
             angular_acceleration: self.angular_acceleration,
             max_linear_velocity: self.max_linear_velocity,
             max_angular_velocity: self.max_angular_velocity,
+            hull_regen: self.hull_regen,
+
+            weapon_slots,
+            shield: self.shield.map(ShieldDataJson::parse),
+
+            detector_range: self.detector_range.max(0.0),
+            signature: self.signature.clamp(0.0, MAX_SIGNATURE),
+
+            ai: {
+                let mut ai = self.ai;
+                ai.verify();
+                ai
+            },
+
+            leave_wreck: self.leave_wreck,
 
             on_new: self.on_new,
-        }
+        })
     }
 }
 
@@ -424,7 +912,6 @@ impl Default for HullShapeJson {
 // ####################################################################################
 
 // TODO: Inventory
-// TODO: Turret
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 #[serde(default)]
 pub struct EntitySave {
@@ -432,14 +919,23 @@ pub struct EntitySave {
 
     pub owner: Option<ClientId>,
 
-    position: Isometry2<f32>,
-    linvel: Vector2<f32>,
-    angvel: f32,
+    pub position: Isometry2<f32>,
+    pub linvel: Vector2<f32>,
+    pub angvel: f32,
 
     hull: f32,
     armor_cells: ArmorCells,
 
-    modifier_saves: SmallVec<[ModifierSave; 4]>,
+    turrets: Vec<Turret>,
+    shield: ShieldSave,
+
+    orders: Vec<Order>,
+    /// Unknown modifiers are dropped.
+    #[serde(with = "crate::util::tolerant_vec")]
+    modifier_saves: Vec<ModifierSave>,
+    /// Data's on new events were applied.
+    spawned: bool,
+    pub wreck: bool,
 }
 impl EntitySave {
     pub fn new(
@@ -457,23 +953,78 @@ impl EntitySave {
             angvel,
             hull: data.hull_max,
             armor_cells: data.armor_cells.clone(),
-            modifier_saves: SmallVec::new(),
+            turrets: data.weapon_slots.iter().map(Turret::new).collect(),
+            shield: ShieldSave {
+                wish_up: true,
+                ..Default::default()
+            },
+            orders: Vec::new(),
+            modifier_saves: Vec::new(),
+            spawned: false,
+            wreck: false,
+        }
+    }
+
+    /// Remains of a destroyed entity with reduced hull and no armor.
+    pub fn new_wreck(
+        data: EntityDataId,
+        position: Isometry2<f32>,
+        linvel: Vector2<f32>,
+        angvel: f32,
+    ) -> Self {
+        Self {
+            data,
+            owner: None,
+            position,
+            linvel,
+            angvel,
+            hull: data.hull_max * WRECK_HULL,
+            armor_cells: smallvec::smallvec![0; data.armor_cells.len()],
+            turrets: Vec::new(),
+            shield: Default::default(),
+            orders: Vec::new(),
+            modifier_saves: Vec::new(),
+            spawned: true,
+            wreck: true,
         }
     }
 
+    /// Decay a saved wreck by `seconds`.
+    /// Returns if anything is left.
+    pub fn decay_wreck(&mut self, seconds: f32) -> bool {
+        self.hull -= wreck_decay_rate(&self.data) * seconds.max(0.0);
+        self.hull > 0.0
+    }
+
     pub fn verify(&mut self) {
+        self.orders.retain(Order::is_valid);
+        self.orders.truncate(MAX_ORDERS);
+
         self.armor_cells.resize(
             self.data.armor_cells_size.x as usize * self.data.armor_cells_size.y as usize,
             0,
         );
+
+        let slots = &self.data.weapon_slots;
+        self.turrets.truncate(slots.len());
+        for slot in slots[self.turrets.len()..].iter() {
+            self.turrets.push(Turret::new(slot));
+        }
+        for (turret, slot) in self.turrets.iter_mut().zip(slots.iter()) {
+            if let Some(builtin_weapon) = slot.builtin_weapon() {
+                turret.weapon = Some(builtin_weapon);
+            } else if turret.weapon.is_some_and(|weapon| !slot.fits(&weapon)) {
+                turret.weapon = None;
+            }
+        }
     }
 }
 
-// TODO: how to handle bad enum when deserializing?
-#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 enum ModifierSave {
-    #[default]
-    RemoveThis,
+    AiShip { task: AiTask },
+    AiSeek,
+    Stat(StatModifier),
 }
 
 // ####################################################################################
@@ -490,7 +1041,7 @@ fn test_serialize_data_json() {
             armor_max: 123.0,
             armor_cells_translation: Vector2::new(-1.5, -1.5),
             armor_cells_size: Vector2::new(3, 3),
-            armor_cells: (0..3 * 3).into_iter().map(|v| v as f32 * 0.33).collect(),
+            armor_cells: (0..3 * 3).map(|v| v as f32 * 0.33).collect(),
             shape_translation: Vector2::new(99.4, 78.81),
             shape: HullShapeJson::Polygon {
                 vertices: vec![
@@ -507,12 +1058,53 @@ fn test_serialize_data_json() {
             angular_acceleration: 2.0,
             max_linear_velocity: 3.0,
             max_angular_velocity: 4.0,
-            on_new: vec![EntityEvent::AddAiShip, EntityEvent::AddAiSeek]
+            hull_regen: 0.0,
+            weapon_slots: vec![Default::default()],
+            shield: Some(Default::default()),
+            detector_range: 30.0,
+            signature: 1.0,
+            ai: Default::default(),
+            leave_wreck: true,
+            on_new: vec![
+                EntityEvent::AddAiShip,
+                EntityEvent::AddAiSeek,
+                EntityEvent::ApplyStatModifier(StatModifier {
+                    source: 0,
+                    stat: Stat::MaxLinearVelocity,
+                    add: 0.0,
+                    multiplier: 1.5,
+                    remaining: Some(10.0),
+                    stacking: Stacking::Refresh,
+                })
+            ]
         })
         .unwrap()
     );
 }
 
+#[test]
+fn test_damage_armor_cells() {
+    let size = Vector2::new(4, 3);
+    let mut armor_cells: ArmorCells = smallvec::smallvec![u8::MAX; 12];
+
+    // Fully absorbed by armor.
+    let overflow = damage_armor_cells(&mut armor_cells, size, 100.0, Vector2::new(1, 1), 16.0);
+    approx::assert_relative_eq!(overflow, 0.0);
+    assert!(armor_cells[5] < armor_cells[0]);
+    assert!(armor_cells[0] < u8::MAX);
+    assert_eq!(armor_cells[3], u8::MAX);
+
+    // Hit outside the grid is clamped to the corner and overflow into hull.
+    let overflow = damage_armor_cells(&mut armor_cells, size, 100.0, Vector2::new(9, -9), 1000.0);
+    assert!(overflow > 500.0);
+    assert_eq!(armor_cells[3], 0);
+    assert_eq!(armor_cells[11], u8::MAX);
+
+    // No armor.
+    let overflow = damage_armor_cells(&mut armor_cells, size, 0.0, Vector2::new(1, 1), 10.0);
+    approx::assert_relative_eq!(overflow, 10.0);
+}
+
 #[test]
 fn test_rotation() {
     let a_translation = vector![100.0f32, 200.0];
@@ -542,3 +1134,17 @@ fn test_rotation() {
     println!("{:?}", global_rotation);
     println!("{:?}", rotation_to_target.angle());
 }
+
+/// Default data with some json fields replaced. Leaked to be static like loaded data.
+#[cfg(test)]
+pub fn test_entity_data(fields: serde_json::Value) -> EntityDataId {
+    let mut json = serde_json::to_value(EntityDataJson::default()).unwrap();
+    json.as_object_mut()
+        .unwrap()
+        .extend(fields.as_object().unwrap().clone());
+    let entity_data = serde_json::from_value::<EntityDataJson>(json)
+        .unwrap()
+        .parse(0, &data().weapons)
+        .unwrap();
+    EntityDataId(Box::leak(Box::new(entity_data)))
+}
@@ -0,0 +1,179 @@
+use super::*;
+
+/// Something that happened to an entity this step.
+///
+/// Dispatched to the entity's modifiers and to clients at the end of the step.
+/// Events removing the entity reach its modifiers right away instead.
+#[derive(Clone)]
+pub enum SimulationEvent {
+    /// Hit by a projectile or a collision. Includes damage absorbed by shield.
+    Damaged {
+        entity_id: EntityId,
+        by: Option<EntityId>,
+        amount: f32,
+    },
+    /// Hull reached 0.
+    Died {
+        entity_id: EntityId,
+        /// Last entity to damage this one.
+        by: Option<EntityId>,
+        owner: Option<ClientId>,
+        data: EntityDataId,
+        position: Isometry2<f32>,
+        linvel: Vector2<f32>,
+        angvel: f32,
+        /// Wrecks do not leave another wreck.
+        wreck: bool,
+    },
+    /// Ship left for another simulation.
+    LeftSimulation {
+        entity_id: EntityId,
+        to: SimulationId,
+    },
+    /// Removed from this simulation for any reason.
+    Despawned { entity_id: EntityId },
+}
+impl SimulationEvent {
+    pub fn entity_id(&self) -> EntityId {
+        match self {
+            Self::Damaged { entity_id, .. }
+            | Self::Died { entity_id, .. }
+            | Self::LeftSimulation { entity_id, .. }
+            | Self::Despawned { entity_id } => *entity_id,
+        }
+    }
+}
+
+impl Simulation {
+    pub fn dispatch_events(&mut self) {
+        for event in std::mem::take(&mut self.events) {
+            // Others were delivered on despawn. Id may already be reused by a new entity.
+            if matches!(event, SimulationEvent::Damaged { .. }) {
+                if let Some(entity) = self.entities.get_mut(&event.entity_id()) {
+                    entity.on_event(&event);
+                }
+            }
+
+            match event {
+                SimulationEvent::Died {
+                    entity_id,
+                    by,
+                    owner,
+                    data,
+                    position,
+                    linvel,
+                    angvel,
+                    wreck,
+                } => {
+                    if !wreck && data.leave_wreck {
+                        self.spawn_wreck(data, position, linvel, angvel);
+                    }
+
+                    if let Some(ship_id) = entity_id.to_ship_id() {
+                        self.database_outbound
+                            .queue(DatabaseRequest::DeleteShip { ship_id });
+                    }
+
+                    let killer_owner = by
+                        .and_then(|by| self.entities.get(&by))
+                        .and_then(|killer| killer.owner);
+
+                    // Ship deaths go to the kill feed of everyone.
+                    // Others are only an explosion for those who could see it.
+                    let kill_feed = entity_id.to_ship_id().is_some();
+                    for client in self.clients.values() {
+                        if kill_feed || client.knows(entity_id) {
+                            client.queue(ClientOutbound::EntityDied {
+                                entity_id,
+                                translation: position.translation.vector,
+                                owner,
+                                killer: by,
+                                killer_owner,
+                            });
+                        }
+                    }
+                }
+                SimulationEvent::LeftSimulation { entity_id, to } => {
+                    log::debug!("{:?} left for {:?}", entity_id, to);
+                }
+                SimulationEvent::Damaged { .. } | SimulationEvent::Despawned { .. } => {}
+            }
+        }
+    }
+}
+
+// ####################################################################################
+// ################################### TEST ###########################################
+// ####################################################################################
+
+#[test]
+fn test_died_events() {
+    let (mut sim, mut database_requests) = test_simulation();
+    let leave_wreck = entity::test_entity_data(serde_json::json!({
+        "hull": 10.0,
+        "leave_wreck": true,
+    }));
+    let no_wreck = entity::test_entity_data(serde_json::json!({
+        "hull": 10.0,
+        "leave_wreck": false,
+    }));
+    let wrecks = |sim: &Simulation| sim.entities.values().filter(|entity| entity.wreck).count();
+    let kill = |sim: &mut Simulation, entity_id: EntityId| {
+        sim.entities[&entity_id].take_hull(f32::MAX);
+        sim.step();
+    };
+
+    let ship_id = ShipId::new(sim.simulation_id);
+    let save = EntitySave::new(
+        leave_wreck,
+        None,
+        Isometry2::identity(),
+        Vector2::zeros(),
+        0.0,
+    );
+    let (entity_id, _) = sim.spawn_entity(save.clone(), None, None, Some(ship_id));
+    database_requests.packets::<DatabaseRequest>();
+
+    // Delivered to modifiers by despawn, death before removal.
+    let died = SimulationEvent::Died {
+        entity_id,
+        by: None,
+        owner: None,
+        data: leave_wreck,
+        position: Isometry2::identity(),
+        linvel: Vector2::zeros(),
+        angvel: 0.0,
+        wreck: false,
+    };
+    sim.despawn_entity(entity_id, Some(died));
+    assert!(matches!(
+        sim.events[..],
+        [
+            SimulationEvent::Died { .. },
+            SimulationEvent::Despawned { .. }
+        ]
+    ));
+    sim.events.clear();
+
+    let (entity_id, _) = sim.spawn_entity(save, None, None, Some(ship_id));
+    kill(&mut sim, entity_id);
+    assert!(!sim.entities.contains_key(&entity_id));
+    assert!(database_requests.packets::<DatabaseRequest>().iter().any(
+        |request| matches!(request, DatabaseRequest::DeleteShip { ship_id: id } if *id == ship_id)
+    ));
+    assert_eq!(wrecks(&sim), 1);
+
+    // Wrecks do not leave another wreck.
+    let wreck_id = *sim.entities.keys().next().unwrap();
+    kill(&mut sim, wreck_id);
+    assert_eq!(wrecks(&sim), 0);
+
+    let (entity_id, _) = sim.spawn_entity(
+        EntitySave::new(no_wreck, None, Isometry2::identity(), Vector2::zeros(), 0.0),
+        None,
+        None,
+        None,
+    );
+    kill(&mut sim, entity_id);
+    assert!(sim.entities.is_empty());
+}
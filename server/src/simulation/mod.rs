@@ -1,27 +1,70 @@
+pub mod ai;
 pub mod client;
+pub mod detection;
 pub mod entity;
+pub mod event;
+pub mod order;
 pub mod physics;
+pub mod projectile;
+pub mod shield;
+pub mod stat;
+pub mod turret;
+pub mod util;
+pub mod wreck;
 
 use super::*;
+use ai::*;
 use client::{Client, ClientInbound, ClientOutbound};
+use detection::*;
 use entity::*;
+use event::*;
+use instance::InstanceInbound;
+use metrics::{SimulationMetrics, Stopwatch};
+use order::*;
 use physics::*;
+use projectile::*;
 use rapier2d::prelude::*;
+use shield::*;
+use stat::*;
 use std::ops::Range;
+use turret::*;
+use util::*;
+use wreck::*;
 
 pub const DT: f32 = 1.0 / 20.0;
 pub const DT_MS: u64 = 50;
-const TICK_PER_SECOND: u64 = 1000 / DT_MS;
+/// Steps while sleeping are this many normal steps.
+pub const SLEEP_TICKS: u64 = 8;
+/// Seconds without activity before sleeping.
+const SLEEP_DELAY: f64 = 10.0;
 
 /// How long between simulation saves.
 /// Add some randomness to stagger saves.
 const SAVE_INTERVAL: Range<f64> = 30.0 * 60.0..40.0 * 60.0;
 
+/// Ships arriving from another simulation appear at this distance from the center.
 const RADIUS: f32 = 100.0;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulationMode {
+    /// Stepping every [DT].
+    Active,
+    /// No client and no combat.
+    /// Stepping every [SLEEP_TICKS] `DT` unless woken up.
+    Sleeping,
+}
+
 pub enum SimulationInbound {
     DatabaseSimulationResponse(DatabaseSimulationResponse),
-    NewClient { client_id: ClientId, client: Client },
+    NewClient {
+        client_id: ClientId,
+        client: Client,
+    },
+    /// Disconnect the client if it is still this session.
+    KickClient {
+        client_id: ClientId,
+        session_id: u64,
+    },
     SaveRequest,
 }
 
@@ -34,7 +77,12 @@ pub struct Simulation {
 
     /// Fixed time step since start of simulation.
     sim_time: f64,
+    /// Multiple of [DT]. Bigger while sleeping.
     sim_dt: f32,
+    mode: SimulationMode,
+    /// Sim time of the last client or combat.
+    last_activity: f64,
+    metrics: SimulationMetrics,
 
     /// Entities always have 1 rigid body and 1 collider.
     ///
@@ -46,8 +94,16 @@ pub struct Simulation {
     next_entity_id: EntityId,
     entities: IndexMap<EntityId, Entity, RandomState>,
 
+    projectiles: Vec<Projectile>,
+    /// Projectiles spawned this step. Sent to clients.
+    new_projectiles: Vec<Projectile>,
+
+    /// Dispatched at the end of the step.
+    events: Vec<SimulationEvent>,
+
     database_outbound: ConnectionOutbound,
     simulation_inbound: Receiver<SimulationInbound>,
+    instance_outbound: Sender<InstanceInbound>,
 
     clients: IndexMap<ClientId, Client, RandomState>,
 }
@@ -56,64 +112,53 @@ impl Simulation {
         simulation_id: SimulationId,
         database_outbound: ConnectionOutbound,
         simulation_inbound: Receiver<SimulationInbound>,
+        instance_outbound: Sender<InstanceInbound>,
         save: SimulationSave,
         next_ship_id: ShipId,
     ) -> Self {
-        Self {
+        let mut s = Self {
             sim_time: 0.0,
             sim_dt: DT,
+            mode: SimulationMode::Active,
+            last_activity: 0.0,
+            metrics: metrics::metrics().simulation(simulation_id),
             physics: Default::default(),
             next_ship_id,
             next_entity_id: Default::default(),
             entities: Default::default(),
+            projectiles: Default::default(),
+            new_projectiles: Default::default(),
+            events: Default::default(),
             clients: Default::default(),
             simulation_id,
             global_time: global_time(),
-            next_save_global_time: thread_rng().gen_range(SAVE_INTERVAL),
+            next_save_global_time: global_time() + thread_rng().gen_range(SAVE_INTERVAL),
             database_outbound,
             simulation_inbound,
-        }
+            instance_outbound,
+        };
+
+        s.load_wrecks(save);
+
+        s
     }
 
     pub fn step(&mut self) {
+        let step_start = Instant::now();
+        let mut stopwatch = Stopwatch::start();
+
         self.sim_time += self.sim_dt as f64;
         self.global_time = global_time();
 
         // Handle inbound.
         while let Ok(inbound) = self.simulation_inbound.try_recv() {
-            match inbound {
-                SimulationInbound::DatabaseSimulationResponse(response) => match response {
-                    DatabaseSimulationResponse::ClientShips {
-                        client_id,
-                        client_ships,
-                    } => {
-                        if let Some(client) = self.clients.get(&client_id) {
-                            client.queue(ClientOutbound::ClientShips {
-                                ships: client_ships,
-                            });
-                        }
-                    }
-                    DatabaseSimulationResponse::ShipEntered {
-                        ship_id,
-                        save: entity_save,
-                    } => {
-                        self.spawn_entity(entity_save, None, None, Some(ship_id));
-                    }
-                },
-                SimulationInbound::NewClient { client_id, client } => {
-                    client.queue(ClientOutbound::EnteredSystem {
-                        client_id,
-                        system_id: self.simulation_id,
-                    });
-                    self.clients.insert(client_id, client);
-                }
-                SimulationInbound::SaveRequest => {
-                    self.save();
-                }
-            }
+            self.handle_inbound(inbound);
         }
+        stopwatch.lap(&self.metrics.inbound);
 
         // Handle client packets.
+        let mut travels = Vec::new();
+        let mut switches = Vec::new();
         self.clients.retain(|&client_id, client| loop {
             match client.recv() {
                 Ok(packet) => match packet {
@@ -121,9 +166,147 @@ impl Simulation {
                         translation,
                         radius,
                     } => {
-                        // TODO: Cap this
-                        client.view_translation = translation;
-                        client.view_radius = radius;
+                        client.set_view(translation, radius);
+                    }
+                    ClientInbound::TakeControl { entity_id } => {
+                        if let Some(entity) = client
+                            .controlled
+                            .take()
+                            .and_then(|entity_id| self.entities.get_mut(&entity_id))
+                        {
+                            entity.release_control();
+                        }
+
+                        if let Some(entity_id) = EntityId::from_u64(entity_id) {
+                            if let Some(entity) = self.entities.get_mut(&entity_id) {
+                                if entity.owner == Some(client_id) {
+                                    entity.controlled = true;
+                                    client.controlled = Some(entity_id);
+                                }
+                            }
+                        }
+
+                        client.queue(ClientOutbound::ControlledEntity {
+                            entity_id: client.controlled,
+                        });
+                    }
+                    ClientInbound::ReleaseControl => {
+                        if let Some(entity) = client
+                            .controlled
+                            .take()
+                            .and_then(|entity_id| self.entities.get_mut(&entity_id))
+                        {
+                            entity.release_control();
+                        }
+
+                        client.queue(ClientOutbound::ControlledEntity { entity_id: None });
+                    }
+                    ClientInbound::TravelShip {
+                        entity_id,
+                        simulation_id,
+                    } => {
+                        let Some(entity_id) = EntityId::from_u64(entity_id) else {
+                            continue;
+                        };
+                        let Some(to) = SimulationId::from_u32(simulation_id).filter(|to| {
+                            *to != self.simulation_id && data().simulations.contains_key(to)
+                        }) else {
+                            continue;
+                        };
+
+                        if entity_id.to_ship_id().is_some()
+                            && self
+                                .entities
+                                .get(&entity_id)
+                                .is_some_and(|entity| entity.owner == Some(client_id))
+                        {
+                            travels.push((entity_id, to));
+                        }
+                    }
+                    ClientInbound::SwitchSimulation { simulation_id } => {
+                        if let Some(to) = SimulationId::from_u32(simulation_id).filter(|to| {
+                            *to != self.simulation_id && data().simulations.contains_key(to)
+                        }) {
+                            switches.push((client_id, to));
+                            // Leave the remaining packets to the next simulation.
+                            break true;
+                        }
+                    }
+                    ClientInbound::ControlInput {
+                        wish_linvel,
+                        wish_angvel,
+                        wish_aim,
+                        wish_fire,
+                        wish_shield,
+                    } => {
+                        let Some(entity_id) = client.controlled else {
+                            continue;
+                        };
+
+                        // Entity may have been destroyed or changed owner.
+                        match self.entities.get_mut(&entity_id) {
+                            Some(entity) if entity.owner == Some(client_id) => {
+                                entity.wish_linvel = wish_linvel.sanitize();
+                                entity.wish_angvel = wish_angvel.sanitize();
+                                entity.wish_aim = wish_aim.sanitize();
+                                entity.wish_fire = wish_fire;
+                                entity.wish_shield = wish_shield;
+                            }
+                            Some(entity) => {
+                                entity.release_control();
+                                client.controlled = None;
+                                client.queue(ClientOutbound::ControlledEntity { entity_id: None });
+                            }
+                            None => {
+                                client.controlled = None;
+                                client.queue(ClientOutbound::ControlledEntity { entity_id: None });
+                            }
+                        }
+                    }
+                    ClientInbound::SetOrders {
+                        entity_id,
+                        orders,
+                        append,
+                    } => {
+                        let Some(entity_id) = EntityId::from_u64(entity_id) else {
+                            continue;
+                        };
+                        let Some(entity) = self
+                            .entities
+                            .get_mut(&entity_id)
+                            .filter(|entity| entity.owner == Some(client_id))
+                        else {
+                            continue;
+                        };
+
+                        // An entity can not follow or attack itself.
+                        entity.set_orders(
+                            orders
+                                .into_iter()
+                                .filter_map(ClientOrder::parse)
+                                .filter(|order| order.entity_id() != Some(entity_id)),
+                            append,
+                        );
+
+                        client.queue(ClientOutbound::Orders {
+                            entity_id,
+                            orders: entity.orders.iter().cloned().collect(),
+                        });
+                    }
+                    ClientInbound::AckState { tick } => {
+                        client.ack_state(tick);
+                    }
+                    ClientInbound::QueryOnlineCount => {
+                        self.database_outbound.queue(DatabaseRequest::Query(
+                            DatabaseQuery::OnlineCount {
+                                client_id,
+                                from: self.simulation_id,
+                            },
+                        ));
+                    }
+                    ClientInbound::RevokeSessionTokens => {
+                        self.database_outbound
+                            .queue(DatabaseRequest::RevokeSessionTokens { client_id });
                     }
                     ClientInbound::CreateFirstShip => {
                         // TODO: Find a place to spawn it.
@@ -141,30 +324,51 @@ impl Simulation {
                     }
                 },
                 Err(TryRecvError::Empty) => break true,
-                Err(TryRecvError::Disconnected) => break false,
+                Err(TryRecvError::Disconnected) => {
+                    if let Some(entity) = client
+                        .controlled
+                        .and_then(|entity_id| self.entities.get_mut(&entity_id))
+                    {
+                        entity.release_control();
+                    }
+
+                    self.database_outbound
+                        .queue(DatabaseRequest::ClientDisconnected {
+                            client_id,
+                            session_id: client.session_id,
+                        });
+
+                    break false;
+                }
             }
         });
 
-        self.physics.step();
-
-        // TODO Handle physic events.
-        for (a, event) in self.physics.events.0.try_lock().unwrap().iter().copied() {
-            // if let Some(entity) = self.entities.get_mut(&a) {
-            //     entity.take_contact_event(event);
-            // }
-
-            // let b = event.with_entity_id;
-            // let event = ContactEvent {
-            //     collider_id: event.with_collider_id,
-            //     with_entity_id: a,
-            //     with_collider_id: event.collider_id,
-            //     force_direction: event.force_direction,
-            //     force_magnitude: event.force_magnitude,
-            // };
-            // if let Some(entity) = self.entities.get_mut(&b) {
-            //     entity.take_contact_event(event);
-            // }
+        for (entity_id, to) in travels {
+            self.travel_ship(entity_id, to);
+        }
+        for (client_id, to) in switches {
+            self.move_client(client_id, to);
+        }
+        stopwatch.lap(&self.metrics.client_packets);
+
+        self.physics.step(self.sim_dt);
+
+        // Handle physic events.
+        for (entity_id, event) in self.physics.events.0.try_lock().unwrap().drain(..) {
+            if let Some(entity) = self.entities.get_mut(&entity_id) {
+                let amount = entity.take_contact_event(
+                    self.physics.body(entity.rb).position(),
+                    event,
+                    self.sim_dt,
+                );
+                self.events.push(SimulationEvent::Damaged {
+                    entity_id,
+                    by: Some(event.with_entity_id),
+                    amount,
+                });
+            }
         }
+        stopwatch.lap(&self.metrics.physics);
 
         // Update entities.
         let mut i = 0;
@@ -172,34 +376,243 @@ impl Simulation {
             if update_entity_retain(self, i) {
                 i += 1;
             } else {
-                // TODO: Do something with the removed entity?
-                self.entities.swap_remove_index(i);
+                let (&entity_id, entity) = self.entities.get_index(i).unwrap();
+                let body = self.physics.body(entity.rb);
+                let event = SimulationEvent::Died {
+                    entity_id,
+                    by: entity.last_hit_by,
+                    owner: entity.owner,
+                    data: entity.data,
+                    position: *body.position(),
+                    linvel: *body.linvel(),
+                    angvel: body.angvel(),
+                    wreck: entity.wreck,
+                };
+                self.despawn_entity(entity_id, Some(event));
             }
         }
 
+        stopwatch.lap(&self.metrics.entities);
+
+        update_projectiles(self);
+        stopwatch.lap(&self.metrics.projectiles);
+
+        self.dispatch_events();
+
         // Update clients.
+        let detectors = client_detectors(self);
         i = 0;
         while i < self.clients.len() {
-            client::update_client(self, i);
+            let client_detectors = detectors
+                .get(self.clients.get_index(i).unwrap().0)
+                .map(|detectors| detectors.as_slice())
+                .unwrap_or_default();
+            client::update_client(self, i, client_detectors);
+
+            let sent_bytes = self.clients[i].take_sent_bytes();
+            self.metrics.client_sent_bytes.observe(sent_bytes as f64);
+
+            i += 1;
         }
+        stopwatch.lap(&self.metrics.update_clients);
+
+        self.new_projectiles.clear();
+
+        self.update_mode();
 
         // Save.
         if self.global_time > self.next_save_global_time {
             self.save();
         }
+
+        self.metrics.entity_count.set(self.entities.len() as i64);
+        self.metrics.client_count.set(self.clients.len() as i64);
+        self.metrics
+            .collider_count
+            .set(self.physics.collider_count() as i64);
+        self.metrics
+            .sleeping
+            .set((self.mode == SimulationMode::Sleeping) as i64);
+        self.metrics
+            .step
+            .observe(step_start.elapsed().as_secs_f64());
+    }
+
+    fn handle_inbound(&mut self, inbound: SimulationInbound) {
+        match inbound {
+            SimulationInbound::DatabaseSimulationResponse(response) => match response {
+                DatabaseSimulationResponse::ClientShips {
+                    client_id,
+                    client_ships,
+                } => {
+                    if let Some(client) = self.clients.get(&client_id) {
+                        client.queue(ClientOutbound::ClientShips {
+                            ships: client_ships,
+                        });
+                    }
+                }
+                DatabaseSimulationResponse::ShipEntered {
+                    ship_id,
+                    save: entity_save,
+                } => {
+                    self.wake();
+
+                    // Ship may have been sent again.
+                    self.despawn_entity(ship_id.to_entity_id(), None);
+                    self.spawn_entity(entity_save, None, None, Some(ship_id));
+                }
+                DatabaseSimulationResponse::OnlineCount {
+                    client_id,
+                    total,
+                    simulation,
+                } => {
+                    if let Some(client) = self.clients.get(&client_id) {
+                        client.queue(ClientOutbound::OnlineCount { total, simulation });
+                    }
+                }
+            },
+            SimulationInbound::NewClient { client_id, client } => {
+                self.wake();
+
+                client.queue(ClientOutbound::EnteredSystem {
+                    client_id,
+                    system_id: self.simulation_id,
+                });
+                self.database_outbound
+                    .queue(DatabaseRequest::ClientConnected {
+                        client_id,
+                        session_id: client.session_id,
+                        simulation_id: self.simulation_id,
+                    });
+
+                if let Some(old) = self.clients.insert(client_id, client) {
+                    self.kick_client(client_id, old);
+                }
+
+                for (&entity_id, entity) in self.entities.iter() {
+                    if entity.owner == Some(client_id) && !entity.orders.is_empty() {
+                        self.send_orders(entity_id);
+                    }
+                }
+            }
+            SimulationInbound::KickClient {
+                client_id,
+                session_id,
+            } => {
+                if self
+                    .clients
+                    .get(&client_id)
+                    .is_some_and(|client| client.session_id == session_id)
+                {
+                    let client = self.clients.swap_remove(&client_id).unwrap();
+                    self.kick_client(client_id, client);
+                }
+            }
+            SimulationInbound::SaveRequest => {
+                self.save();
+            }
+        }
+    }
+
+    pub fn mode(&self) -> SimulationMode {
+        self.mode
+    }
+
+    /// Notify the entity's owner of its orders.
+    fn send_orders(&self, entity_id: EntityId) {
+        let Some(entity) = self.entities.get(&entity_id) else {
+            return;
+        };
+        if let Some(client) = entity.owner.and_then(|owner| self.clients.get(&owner)) {
+            client.queue(ClientOutbound::Orders {
+                entity_id,
+                orders: entity.orders.iter().cloned().collect(),
+            });
+        }
+    }
+
+    pub fn simulation_id(&self) -> SimulationId {
+        self.simulation_id
+    }
+
+    pub fn metrics(&self) -> &SimulationMetrics {
+        &self.metrics
+    }
+
+    /// A sleeping simulation should step early to handle it.
+    pub fn has_inbound(&self) -> bool {
+        !self.simulation_inbound.is_empty()
+    }
+
+    /// Next step will cover this many [DT] while sleeping.
+    pub fn set_slept_ticks(&mut self, ticks: u64) {
+        self.sim_dt = DT * ticks.clamp(1, SLEEP_TICKS) as f32;
+    }
+
+    fn wake(&mut self) {
+        self.last_activity = self.sim_time;
+        self.set_mode(SimulationMode::Active);
+    }
+
+    fn set_mode(&mut self, mode: SimulationMode) {
+        if self.mode != mode {
+            log::debug!("{:?} is now {:?}", self.simulation_id, mode);
+            self.mode = mode;
+        }
+    }
+
+    fn update_mode(&mut self) {
+        let active = !self.clients.is_empty()
+            || !self.projectiles.is_empty()
+            || self.entities.values().any(|entity| entity.wish_fire);
+        if active {
+            self.last_activity = self.sim_time;
+        }
+
+        if self.sim_time - self.last_activity > SLEEP_DELAY {
+            self.set_mode(SimulationMode::Sleeping);
+        } else {
+            self.set_mode(SimulationMode::Active);
+            self.sim_dt = DT;
+        }
     }
 
-    fn save(&mut self) {
-        self.next_save_global_time = thread_rng().gen_range(SAVE_INTERVAL);
+    /// Queue the wrecks and ships to the database.
+    pub fn save(&mut self) {
+        self.next_save_global_time = self.global_time + thread_rng().gen_range(SAVE_INTERVAL);
 
-        let simulation_save = SimulationSave {};
+        let simulation_save = SimulationSave {
+            wrecks: self
+                .entities
+                .values()
+                .filter(|entity| entity.wreck)
+                .map(|entity| entity.save(self))
+                .collect(),
+            saved_at: self.global_time,
+        };
 
         self.database_outbound
             .queue(DatabaseRequest::SaveSimulation {
                 simulation_id: self.simulation_id,
                 simulation_save,
             });
-        // TODO: Save ships
+
+        let ships = self
+            .entities
+            .iter()
+            .filter_map(|(entity_id, entity)| {
+                entity_id
+                    .to_ship_id()
+                    .map(|ship_id| (ship_id, entity.save(self)))
+            })
+            .collect::<Vec<_>>();
+        if !ships.is_empty() {
+            self.database_outbound.queue(DatabaseRequest::SaveShips {
+                simulation_id: self.simulation_id,
+                ships,
+            });
+        }
+
         // TODO: Save planets?
     }
 
@@ -228,19 +641,120 @@ impl Simulation {
         (entity_id, entity_idx)
     }
 
-    fn remove_entity(&mut self, entity_id: EntityId) {
-        if let Some(entity) = self.entities.swap_remove(&entity_id) {
-            self.physics.remove_body(entity.rb);
-            // TODO:
+    /// Remove the entity from this simulation only.
+    ///
+    /// Its modifiers get `cause` and [SimulationEvent::Despawned] now,
+    /// as it will be gone when events are dispatched.
+    fn despawn_entity(
+        &mut self,
+        entity_id: EntityId,
+        cause: Option<SimulationEvent>,
+    ) -> Option<Entity> {
+        let mut entity = self.entities.swap_remove(&entity_id)?;
+        self.physics.remove_body(entity.rb);
+        for event in cause
+            .into_iter()
+            .chain([SimulationEvent::Despawned { entity_id }])
+        {
+            entity.on_event(&event);
+            self.events.push(event);
+        }
+        Some(entity)
+    }
+}
 
-            if let Some(ship_id) = entity_id.to_ship_id() {
-                self.database_outbound
-                    .queue(DatabaseRequest::DeleteShip { ship_id });
-            }
+impl Simulation {
+    /// Despawn a ship and send it to another simulation through the database.
+    /// Clients controlling the ship follow it.
+    fn travel_ship(&mut self, entity_id: EntityId, to: SimulationId) {
+        let Some(ship_id) = entity_id.to_ship_id() else {
+            return;
+        };
+        let Some(entity) = self.entities.get(&entity_id) else {
+            return;
+        };
+
+        let mut save = entity.save(self);
+        save.position = arrival_position();
+        save.linvel = Vector2::zeros();
+        save.angvel = 0.0;
+
+        if let Some(owner) = save.owner.and_then(|owner| self.clients.get(&owner)) {
+            owner.queue(ClientOutbound::ShipTraveled {
+                entity_id,
+                simulation_id: to,
+            });
         }
+
+        self.despawn_entity(
+            entity_id,
+            Some(SimulationEvent::LeftSimulation { entity_id, to }),
+        );
+
+        self.database_outbound.queue(DatabaseRequest::SaveShip {
+            ship_id,
+            simulation_id: to,
+            save,
+        });
+
+        let following = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.controlled == Some(entity_id))
+            .map(|(client_id, _)| *client_id)
+            .collect::<SmallVec<[ClientId; 1]>>();
+        for client_id in following {
+            self.move_client(client_id, to);
+        }
+    }
+
+    /// Client logged in from elsewhere.
+    fn kick_client(&mut self, client_id: ClientId, client: Client) {
+        if let Some(entity) = client
+            .controlled
+            .and_then(|entity_id| self.entities.get_mut(&entity_id))
+        {
+            entity.release_control();
+        }
+
+        self.database_outbound
+            .queue(DatabaseRequest::ClientDisconnected {
+                client_id,
+                session_id: client.session_id,
+            });
+        client.close("Logged in from elsewhere");
+    }
+
+    /// Hand the client to the instance which will send it to the other simulation.
+    fn move_client(&mut self, client_id: ClientId, to: SimulationId) {
+        let Some(client) = self.clients.swap_remove(&client_id) else {
+            return;
+        };
+
+        if let Some(entity) = client
+            .controlled
+            .and_then(|entity_id| self.entities.get_mut(&entity_id))
+        {
+            entity.release_control();
+        }
+
+        let _ = self.instance_outbound.send(InstanceInbound::MoveClient {
+            client_id,
+            client,
+            to,
+        });
     }
 }
 
+/// Random position at the edge facing the center.
+fn arrival_position() -> Isometry2<f32> {
+    let angle = thread_rng().gen_range(0.0..TAU);
+    Isometry2::new(
+        Vector2::new(angle.cos(), angle.sin()) * RADIUS,
+        angle + std::f32::consts::PI,
+    )
+}
+
 fn global_time() -> f64 {
     std::time::UNIX_EPOCH
         .elapsed()
@@ -248,15 +762,32 @@ fn global_time() -> f64 {
         .as_secs_f64()
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 #[serde(default)]
 pub struct SimulationSave {
-    // TODO: Debris
+    pub wrecks: Vec<EntitySave>,
+    /// Global time of the save. Wrecks decay from then when loaded.
+    pub saved_at: f64,
     // TODO: items
     // TODO: planets state
 }
-impl Default for SimulationSave {
-    fn default() -> Self {
-        Self {}
-    }
+
+// ####################################################################################
+// ################################### TEST ###########################################
+// ####################################################################################
+
+/// Without instance. Requests to the database are kept in the returned outbound.
+#[cfg(test)]
+pub fn test_simulation() -> (Simulation, connection::TestOutbound) {
+    let simulation_id = SimulationId::from_u32(1).unwrap();
+    let (database_outbound, database_requests) = connection::TestOutbound::new();
+    let simulation = Simulation::new(
+        simulation_id,
+        database_outbound,
+        unbounded().1,
+        unbounded().0,
+        Default::default(),
+        ShipId::new(simulation_id),
+    );
+    (simulation, database_requests)
 }
@@ -0,0 +1,145 @@
+use super::*;
+use std::collections::VecDeque;
+
+/// Orders past this are dropped.
+pub const MAX_ORDERS: usize = 16;
+pub const MAX_WAYPOINTS: usize = 8;
+
+pub type Orders = VecDeque<Order>;
+
+/// Standing order given by a ship's owner. Executed by [AiShip] in queue order.
+///
+/// Orders referencing a missing entity are done.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Order {
+    /// Done once stopped at position.
+    MoveTo { position: Vector2<f32> },
+    /// Stay close to an entity.
+    Follow { entity_id: EntityId },
+    /// Engage an entity regardless of range.
+    Attack { entity_id: EntityId },
+    /// Loop through waypoints. Never done.
+    Patrol {
+        waypoints: SmallVec<[Vector2<f32>; 4]>,
+        next: usize,
+    },
+    /// Stay close to an entity and engage hostiles around it.
+    Defend { entity_id: EntityId },
+    /// Take hull from a wreck until nothing is left.
+    Salvage { entity_id: EntityId },
+}
+
+impl Order {
+    /// Rules shared by client orders and saved ones.
+    pub fn is_valid(&self) -> bool {
+        let finite = |v: &Vector2<f32>| v.iter().all(|v| v.is_finite());
+
+        match self {
+            Self::MoveTo { position } => finite(position),
+            Self::Patrol { waypoints, .. } => {
+                !waypoints.is_empty()
+                    && waypoints.len() <= MAX_WAYPOINTS
+                    && waypoints.iter().all(finite)
+            }
+            Self::Follow { .. }
+            | Self::Attack { .. }
+            | Self::Defend { .. }
+            | Self::Salvage { .. } => true,
+        }
+    }
+
+    /// Entity the order is about.
+    pub fn entity_id(&self) -> Option<EntityId> {
+        match self {
+            Self::Follow { entity_id }
+            | Self::Attack { entity_id }
+            | Self::Defend { entity_id }
+            | Self::Salvage { entity_id } => Some(*entity_id),
+            Self::MoveTo { .. } | Self::Patrol { .. } => None,
+        }
+    }
+}
+
+/// [Order] as sent by clients.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ClientOrder {
+    MoveTo { position: Vector2<f32> },
+    Follow { entity_id: u64 },
+    Attack { entity_id: u64 },
+    Patrol { waypoints: Vec<Vector2<f32>> },
+    Defend { entity_id: u64 },
+    Salvage { entity_id: u64 },
+}
+impl ClientOrder {
+    /// None if invalid.
+    pub fn parse(self) -> Option<Order> {
+        let order = match self {
+            Self::MoveTo { position } => Order::MoveTo { position },
+            Self::Follow { entity_id } => Order::Follow {
+                entity_id: EntityId::from_u64(entity_id)?,
+            },
+            Self::Attack { entity_id } => Order::Attack {
+                entity_id: EntityId::from_u64(entity_id)?,
+            },
+            // Checked before collecting into the small vec.
+            Self::Patrol { waypoints } if waypoints.len() > MAX_WAYPOINTS => return None,
+            Self::Patrol { waypoints } => Order::Patrol {
+                waypoints: waypoints.into_iter().collect(),
+                next: 0,
+            },
+            Self::Defend { entity_id } => Order::Defend {
+                entity_id: EntityId::from_u64(entity_id)?,
+            },
+            Self::Salvage { entity_id } => Order::Salvage {
+                entity_id: EntityId::from_u64(entity_id)?,
+            },
+        };
+        order.is_valid().then_some(order)
+    }
+}
+
+// ####################################################################################
+// ################################### TEST ###########################################
+// ####################################################################################
+
+#[test]
+fn test_client_order_parse() {
+    assert!(ClientOrder::MoveTo {
+        position: Vector2::new(f32::NAN, 0.0)
+    }
+    .parse()
+    .is_none());
+    assert!(ClientOrder::Attack { entity_id: 0 }.parse().is_none());
+    assert!(ClientOrder::Patrol { waypoints: vec![] }.parse().is_none());
+    assert!(ClientOrder::Patrol {
+        waypoints: vec![Vector2::zeros(); MAX_WAYPOINTS + 1]
+    }
+    .parse()
+    .is_none());
+
+    assert!(matches!(
+        ClientOrder::Patrol {
+            waypoints: vec![Vector2::zeros(); 2]
+        }
+        .parse(),
+        Some(Order::Patrol { next: 0, .. })
+    ));
+}
+
+#[test]
+fn test_order_is_valid() {
+    assert!(!Order::Patrol {
+        waypoints: SmallVec::new(),
+        next: 3,
+    }
+    .is_valid());
+    assert!(!Order::MoveTo {
+        position: Vector2::new(0.0, f32::INFINITY)
+    }
+    .is_valid());
+    assert!(Order::Patrol {
+        waypoints: smallvec::smallvec![Vector2::zeros()],
+        next: 3,
+    }
+    .is_valid());
+}
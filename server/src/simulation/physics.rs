@@ -44,6 +44,7 @@ pub mod group {
         .union(GROUP_PROJECTILE);
 
     pub const GROUPS_SHIP: InteractionGroups = InteractionGroups::new(GROUP_SHIP, GROUP_ALL);
+    pub const GROUPS_DEBRIS: InteractionGroups = InteractionGroups::new(GROUP_DEBRIS, GROUP_ALL);
     // pub const GROUPS_ENTITY: InteractionGroups = InteractionGroups::new(GROUP_SHIP, GROUP_ALL);
 }
 
@@ -62,12 +63,20 @@ pub struct Physics {
     pub events: PhysicsEventCollector,
 }
 impl Physics {
-    pub fn step(&mut self) {
+    pub fn collider_count(&self) -> usize {
+        self.colliders.len()
+    }
+
+    pub fn step(&mut self, dt: f32) {
         self.events.0.try_lock().unwrap().clear();
 
         self.physics_pipeline.step(
             &vector![0.0, 0.0],
-            &INTEGRATION_PARAMETERS,
+            &IntegrationParameters {
+                dt,
+                min_ccd_dt: dt / 100.0,
+                ..INTEGRATION_PARAMETERS
+            },
             &mut self.islands,
             &mut self.broad_phase,
             &mut self.narrow_phase,
@@ -123,7 +132,50 @@ impl Physics {
         rb
     }
 
-    // TODO: Add/remove/set shield
+    /// Replace the groups of all the body's colliders.
+    pub fn set_groups(&mut self, rb: RigidBodyHandle, groups: InteractionGroups) {
+        for &collider in self.bodies[rb].colliders() {
+            self.colliders[collider].set_collision_groups(groups);
+        }
+    }
+
+    /// Shield has no mass and is disabled.
+    /// Contacts outside `half_arc` are ignored, see [ShieldData::covers].
+    pub fn add_shield(
+        &mut self,
+        rb: RigidBodyHandle,
+        entity_id: EntityId,
+        radius: f32,
+        half_arc: f32,
+        filter: Group,
+    ) -> ColliderHandle {
+        let coll = ColliderBuilder::ball(radius)
+            .collision_groups(InteractionGroups::new(group::GROUP_SHIELD, filter))
+            .density(0.0)
+            .enabled(false)
+            .user_data(UserData::pack_shield(entity_id, half_arc))
+            .active_hooks(ActiveHooks::FILTER_CONTACT_PAIRS | ActiveHooks::MODIFY_SOLVER_CONTACTS)
+            .active_events(ActiveEvents::CONTACT_FORCE_EVENTS)
+            .contact_force_event_threshold(DEFAULT_CONTACT_FORCE_EVENT_THRESHOLD)
+            .friction(DEFAULT_FRICTION)
+            .restitution(DEFAULT_RESTITUTION)
+            .build();
+
+        self.colliders
+            .insert_with_parent(coll, rb, &mut self.bodies)
+    }
+
+    /// ## Panic:
+    /// Handle is invalid.
+    pub fn set_shield_enabled(&mut self, handle: ColliderHandle, enabled: bool) {
+        self.colliders[handle].set_enabled(enabled);
+    }
+
+    /// ## Panic:
+    /// Handle is invalid.
+    pub fn remove_shield(&mut self, handle: ColliderHandle) {
+        self.remove_collider(handle);
+    }
 
     /// Remove the body and its colliders.
     /// ## Panic:
@@ -149,6 +201,32 @@ impl Physics {
             .unwrap()
     }
 
+    /// Ignore bodies with the same group ignore.
+    /// Returns the hit collider and time of impact.
+    pub fn cast_ray(
+        &self,
+        ray: &Ray,
+        max_toi: f32,
+        group_ignore: u64,
+        exclude: &[ColliderHandle],
+    ) -> Option<(ColliderHandle, f32)> {
+        let predicate = |handle, collider: &Collider| {
+            !exclude.contains(&handle)
+                && collider
+                    .parent()
+                    .is_none_or(|rb| self.bodies[rb].user_data.group_ignore() != group_ignore)
+        };
+
+        self.query_pipeline.cast_ray(
+            &self.bodies,
+            &self.colliders,
+            ray,
+            max_toi,
+            true,
+            QueryFilter::new().predicate(&predicate),
+        )
+    }
+
     // pub fn intersect_broad(&self, aabb: &Aabb) {
     //     self.query_pipeline.colliders_with_aabb_intersecting_aabb(aabb, callback)
     // }
@@ -198,12 +276,26 @@ impl PhysicsHooks for Hooks {
         self.filter_contact_pair(context).is_some()
     }
 
-    fn modify_solver_contacts(&self, _context: &mut ContactModificationContext) {}
+    /// Shield collider is a full ball. Drop its contacts outside the arc.
+    fn modify_solver_contacts(&self, context: &mut ContactModificationContext) {
+        for handle in [context.collider1, context.collider2] {
+            let collider = &context.colliders[handle];
+            if !collider.user_data.shield() {
+                continue;
+            }
+
+            let position = collider.position();
+            let half_arc = collider.user_data.shield_half_arc();
+            context.solver_contacts.retain(|contact| {
+                ShieldData::arc_covers(half_arc, position.inverse_transform_point(&contact.point))
+            });
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct ContactEvent {
-    // pub entity_id: EntityId,
+    /// If the contact was with this entity's shield.
     pub shield: bool,
 
     pub with_entity_id: EntityId,
@@ -218,6 +310,7 @@ pub struct ContactEvent {
     pub force_magnitude: f32,
 }
 
+/// Contact events are pushed once for each entity involved.
 #[derive(Default)]
 pub struct PhysicsEventCollector(pub Arc<Mutex<Vec<(EntityId, ContactEvent)>>>);
 impl EventHandler for PhysicsEventCollector {
@@ -238,55 +331,76 @@ impl EventHandler for PhysicsEventCollector {
         contact_pair: &ContactPair,
         total_force_magnitude: Real,
     ) {
-        // TODO: only take event when force at a point is > some value
-        // contact_pair.
-        let mut point1 = Point2::default();
-        log::debug!("num contact manifold: {}", contact_pair.manifolds.len());
-        for m in contact_pair.manifolds.iter() {
-            // m.points
-            log::debug!("contact points: {:?}", m.points);
-            for p in m.points.iter() {
-                point1 = p.local_p1;
+        // Find the contact point which received the strongest impulse.
+        let mut max_impulse = 0.0;
+        let mut local_point = None;
+        for manifold in contact_pair.manifolds.iter() {
+            for point in manifold.points.iter() {
+                if point.data.impulse > max_impulse {
+                    max_impulse = point.data.impulse;
+                    local_point = Some(point.local_p1);
+                }
             }
         }
+        let Some(local_point) = local_point else {
+            return;
+        };
 
         let event = ContactForceEvent::from_contact_pair(dt, contact_pair, total_force_magnitude);
 
-        let a = colliders[contact_pair.collider1].user_data;
-        let b = colliders[contact_pair.collider2].user_data;
-
-        let entity_id = a.entity_id();
-        let event = ContactEvent {
-            shield: false,
-
-            with_entity_id: b.entity_id(),
-            with_shield: false,
+        let collider1 = &colliders[contact_pair.collider1];
+        let point = collider1.position() * local_point;
 
-            point: point1,
-            force_direction: event.max_force_direction,
-            force_magnitude: event.max_force_magnitude,
-        };
+        let a = collider1.user_data;
+        let b = colliders[contact_pair.collider2].user_data;
 
-        self.0.try_lock().unwrap().push((entity_id, event));
+        let mut events = self.0.try_lock().unwrap();
+        events.push((
+            a.entity_id(),
+            ContactEvent {
+                shield: a.shield(),
+                with_entity_id: b.entity_id(),
+                with_shield: b.shield(),
+                point,
+                force_direction: event.max_force_direction,
+                force_magnitude: event.max_force_magnitude,
+            },
+        ));
+        events.push((
+            b.entity_id(),
+            ContactEvent {
+                shield: b.shield(),
+                with_entity_id: a.entity_id(),
+                with_shield: a.shield(),
+                point,
+                force_direction: -event.max_force_direction,
+                force_magnitude: event.max_force_magnitude,
+            },
+        ));
     }
 }
 
 /// Body:
 /// - EntityId: 64
 /// - Group ignore: 64
+///
 /// Collider:
 /// - EntityId: 64
 /// - Is shield: 1
+/// - Shield half arc: 32 (from 96)
 pub trait UserData {
     const ID_TYPE_OFFSET: u32 = u64::BITS;
     const GROUP_IGNORE_OFFSET: u32 = Self::ID_TYPE_OFFSET + 4;
     fn pack_body(entity_id: EntityId, group_ignore: u64) -> Self;
     fn pack_colider(entity_id: EntityId, shield: bool) -> Self;
+    fn pack_shield(entity_id: EntityId, half_arc: f32) -> Self;
     fn entity_id(self) -> EntityId;
     /// Only valid for body.
     fn group_ignore(self) -> u64;
     /// Only valid for collider.
     fn shield(self) -> bool;
+    /// Only valid for shield collider.
+    fn shield_half_arc(self) -> f32;
 }
 impl UserData for u128 {
     fn pack_body(entity_id: EntityId, group_ignore: u64) -> Self {
@@ -297,6 +411,10 @@ impl UserData for u128 {
         entity_id.as_u64() as u128 | (shield as u128) << 64
     }
 
+    fn pack_shield(entity_id: EntityId, half_arc: f32) -> Self {
+        Self::pack_colider(entity_id, true) | (half_arc.to_bits() as u128) << 96
+    }
+
     fn entity_id(self) -> EntityId {
         EntityId::from_u64(self as u64).unwrap()
     }
@@ -306,6 +424,95 @@ impl UserData for u128 {
     }
 
     fn shield(self) -> bool {
-        self >> 64 != 0
+        (self >> 64) & 1 != 0
+    }
+
+    fn shield_half_arc(self) -> f32 {
+        f32::from_bits((self >> 96) as u32)
     }
 }
+
+// ####################################################################################
+// ################################### TEST ###########################################
+// ####################################################################################
+
+#[test]
+fn test_add_remove_shield() {
+    let data = entity::test_entity_data(serde_json::json!({}));
+    let entity_id = EntityId::default();
+
+    let mut physics = Physics::default();
+    let rb = physics.add_body(
+        Isometry2::identity(),
+        Vector2::zeros(),
+        0.0,
+        data,
+        entity_id,
+        1,
+    );
+    let shield = physics.add_shield(rb, entity_id, 2.0, std::f32::consts::PI, group::GROUP_ALL);
+    assert_eq!(physics.collider_count(), 2);
+    assert!(physics.collider(shield).user_data.shield());
+    assert!(!physics.collider(shield).is_enabled());
+
+    physics.set_shield_enabled(shield, true);
+    assert!(physics.collider(shield).is_enabled());
+
+    physics.remove_shield(shield);
+    assert_eq!(physics.collider_count(), 1);
+    assert_eq!(physics.body(rb).colliders().len(), 1);
+}
+
+#[test]
+fn test_shield_arc() {
+    let data = entity::test_entity_data(serde_json::json!({
+        "density": 1.0,
+        "mass_radius": 0.5,
+        "memberships": 1,
+        "filter": u32::MAX,
+    }));
+
+    // Closest the other body gets when thrown at a front shield.
+    let closest = |from: f32| {
+        let mut physics = Physics::default();
+        let entity_id = EntityId::default();
+        let rb = physics.add_body(
+            Isometry2::identity(),
+            Vector2::zeros(),
+            0.0,
+            data,
+            entity_id,
+            1,
+        );
+        let shield = physics.add_shield(
+            rb,
+            entity_id,
+            3.0,
+            std::f32::consts::FRAC_PI_2,
+            group::GROUP_ALL,
+        );
+        physics.set_shield_enabled(shield, true);
+
+        let other = physics.add_body(
+            Isometry2::translation(from, 0.0),
+            Vector2::new(-from.signum() * 10.0, 0.0),
+            0.0,
+            data,
+            EntityId::from_u64(1).unwrap(),
+            2,
+        );
+
+        let mut closest = f32::MAX;
+        for _ in 0..40 {
+            physics.step(DT);
+            let distance = physics.body(other).translation() - physics.body(rb).translation();
+            closest = closest.min(distance.magnitude());
+        }
+        closest
+    };
+
+    // Stopped by the shield in front.
+    assert!(closest(8.0) > 3.0);
+    // Not stopped from behind until the hulls touch.
+    assert!(closest(-8.0) < 1.5);
+}
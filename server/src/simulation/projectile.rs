@@ -0,0 +1,261 @@
+use super::*;
+
+/// Simple projectile which is not part of the physics simulation.
+/// Hits are found with a ray cast every step.
+///
+/// Client only receive the spawn and extrapolate the rest.
+#[derive(Debug, Clone, Copy)]
+pub struct Projectile {
+    pub weapon: WeaponDataId,
+    pub translation: Vector2<f32>,
+    pub velocity: Vector2<f32>,
+    /// Seconds left before the projectile disappear.
+    pub lifetime: f32,
+    pub damage: f32,
+    /// Will not hit entities in the same group ignore.
+    pub group_ignore: u64,
+    /// Entity which fired this.
+    pub source: EntityId,
+}
+impl Projectile {
+    /// `angle` and `translation` are in world space.
+    pub fn new(
+        weapon: WeaponDataId,
+        translation: Vector2<f32>,
+        angle: f32,
+        inherited_velocity: Vector2<f32>,
+        group_ignore: u64,
+        source: EntityId,
+    ) -> Self {
+        Self {
+            weapon,
+            translation,
+            velocity: Vector2::new(angle.cos(), angle.sin()) * weapon.projectile_speed
+                + inherited_velocity,
+            lifetime: weapon.projectile_lifetime(),
+            damage: weapon.damage,
+            group_ignore,
+            source,
+        }
+    }
+}
+
+pub fn update_projectiles(sim: &mut Simulation) {
+    let mut i = 0;
+    while i < sim.projectiles.len() {
+        let projectile = &mut sim.projectiles[i];
+
+        let ray = Ray::new(projectile.translation.into(), projectile.velocity);
+        let max_toi = projectile.lifetime.min(sim.sim_dt);
+
+        // Shields which did not absorb all the damage are ignored.
+        let mut exclude: SmallVec<[ColliderHandle; 2]> = SmallVec::new();
+        let mut hit = false;
+        while let Some((collider, toi)) =
+            sim.physics
+                .cast_ray(&ray, max_toi, projectile.group_ignore, &exclude)
+        {
+            let point = ray.point_at(toi);
+            let user_data = sim.physics.collider(collider).user_data;
+
+            let entity_id = user_data.entity_id();
+            let Some(entity) = sim.entities.get_mut(&entity_id) else {
+                hit = true;
+                break;
+            };
+            let local_point = sim
+                .physics
+                .body(entity.rb)
+                .position()
+                .inverse_transform_point(&point);
+
+            entity.last_hit_by = Some(projectile.source);
+
+            // Shield only reports what it absorbed, the rest goes to the hull behind.
+            let damage = projectile.damage;
+            let taken = if user_data.shield() {
+                projectile.damage = entity.damage_shield(local_point, damage);
+                damage - projectile.damage
+            } else {
+                entity.damage(local_point, damage);
+                damage
+            };
+            sim.events.push(SimulationEvent::Damaged {
+                entity_id,
+                by: Some(projectile.source),
+                amount: taken,
+            });
+
+            if user_data.shield() && projectile.damage > 0.0 {
+                exclude.push(collider);
+                continue;
+            }
+
+            hit = true;
+            break;
+        }
+
+        if hit {
+            sim.projectiles.swap_remove(i);
+            continue;
+        }
+
+        projectile.translation += projectile.velocity * max_toi;
+        projectile.lifetime -= max_toi;
+
+        if projectile.lifetime <= 0.0 {
+            sim.projectiles.swap_remove(i);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+// ####################################################################################
+// ################################### TEST ###########################################
+// ####################################################################################
+
+#[cfg(test)]
+fn test_projectile(translation: Vector2<f32>, velocity: Vector2<f32>, damage: f32) -> Projectile {
+    Projectile {
+        weapon: WeaponDataId(&data().weapons[0]),
+        translation,
+        velocity,
+        lifetime: 1.0,
+        damage,
+        group_ignore: 1,
+        source: EntityId::from_u64(1).unwrap(),
+    }
+}
+
+#[test]
+fn test_projectile_hit() {
+    let (mut sim, _) = test_simulation();
+    let data = entity::test_entity_data(serde_json::json!({ "hull": 10.0 }));
+    let (entity_id, entity_idx) = sim.spawn_entity(
+        EntitySave::new(
+            data,
+            None,
+            Isometry2::translation(5.0, 0.0),
+            Vector2::zeros(),
+            0.0,
+        ),
+        Some(2),
+        None,
+        None,
+    );
+    sim.physics.step(DT);
+
+    sim.projectiles.push(test_projectile(
+        Vector2::zeros(),
+        Vector2::new(200.0, 0.0),
+        4.0,
+    ));
+    update_projectiles(&mut sim);
+
+    assert!(sim.projectiles.is_empty());
+    approx::assert_relative_eq!(sim.entities[entity_idx].hull_ratio(), 0.6);
+    assert_eq!(sim.entities[entity_idx].last_hit_by, EntityId::from_u64(1));
+    assert!(matches!(
+        sim.events[..],
+        [SimulationEvent::Damaged { entity_id: id, amount, .. }] if id == entity_id && amount == 4.0
+    ));
+}
+
+#[test]
+fn test_projectile_expire() {
+    let (mut sim, _) = test_simulation();
+
+    let mut projectile = test_projectile(Vector2::zeros(), Vector2::new(10.0, 0.0), 4.0);
+    projectile.lifetime = DT * 1.5;
+    sim.projectiles.push(projectile);
+
+    update_projectiles(&mut sim);
+    assert_eq!(sim.projectiles.len(), 1);
+    approx::assert_relative_eq!(sim.projectiles[0].translation.x, 10.0 * DT);
+
+    update_projectiles(&mut sim);
+    assert!(sim.projectiles.is_empty());
+}
+
+#[test]
+fn test_projectile_through_shield() {
+    let (mut sim, _) = test_simulation();
+    let data = entity::test_entity_data(serde_json::json!({
+        "hull": 20.0,
+        "shield": { "radius": 2.0, "arc": 7.0, "flux_max": 3.0, "flux_regen": 0.0 },
+    }));
+    let (_, entity_idx) = sim.spawn_entity(
+        EntitySave::new(
+            data,
+            None,
+            Isometry2::translation(5.0, 0.0),
+            Vector2::zeros(),
+            0.0,
+        ),
+        Some(2),
+        None,
+        None,
+    );
+    let entity = &mut sim.entities[entity_idx];
+    entity.shield.as_mut().unwrap().update(
+        &mut sim.physics,
+        entity.data.shield.as_ref().unwrap(),
+        true,
+        0.0,
+    );
+    sim.physics.step(DT);
+
+    sim.projectiles.push(test_projectile(
+        Vector2::zeros(),
+        Vector2::new(200.0, 0.0),
+        10.0,
+    ));
+    update_projectiles(&mut sim);
+
+    // Shield absorbed up to its flux max, the rest went through to the hull.
+    assert!(sim.projectiles.is_empty());
+    let entity = &sim.entities[entity_idx];
+    assert!(entity.shield.as_ref().unwrap().overloaded);
+    approx::assert_relative_eq!(entity.hull_ratio(), 13.0 / 20.0);
+    let amounts = sim
+        .events
+        .iter()
+        .map(|event| match event {
+            SimulationEvent::Damaged { amount, .. } => *amount,
+            _ => 0.0,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(amounts, [3.0, 7.0]);
+}
+
+#[test]
+fn test_projectile_group_ignore() {
+    let (mut sim, _) = test_simulation();
+    let data = entity::test_entity_data(serde_json::json!({ "hull": 10.0 }));
+    let (_, entity_idx) = sim.spawn_entity(
+        EntitySave::new(
+            data,
+            None,
+            Isometry2::translation(5.0, 0.0),
+            Vector2::zeros(),
+            0.0,
+        ),
+        Some(1),
+        None,
+        None,
+    );
+    sim.physics.step(DT);
+
+    sim.projectiles.push(test_projectile(
+        Vector2::zeros(),
+        Vector2::new(200.0, 0.0),
+        4.0,
+    ));
+    update_projectiles(&mut sim);
+
+    // Went through its owner's group.
+    assert_eq!(sim.projectiles.len(), 1);
+    approx::assert_relative_eq!(sim.entities[entity_idx].hull_ratio(), 1.0);
+    assert!(sim.events.is_empty());
+}
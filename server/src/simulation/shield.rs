@@ -0,0 +1,173 @@
+use super::*;
+
+/// An entity's shield.
+///
+/// Damage to the shield become flux.
+/// Shield is overloaded when flux reach its maximum
+/// and can not be raised until flux is fully dissipated.
+pub struct Shield {
+    pub collider: ColliderHandle,
+    pub flux: f32,
+    /// If the shield collider is enabled.
+    pub up: bool,
+    pub overloaded: bool,
+}
+impl Shield {
+    pub fn new(
+        physics: &mut Physics,
+        rb: RigidBodyHandle,
+        entity_id: EntityId,
+        data: &ShieldData,
+        filter: Group,
+        save: ShieldSave,
+    ) -> Self {
+        let collider = physics.add_shield(rb, entity_id, data.radius, data.half_arc, filter);
+
+        let mut s = Self {
+            collider,
+            flux: save.flux.clamp(0.0, data.flux_max),
+            up: true,
+            overloaded: save.overloaded,
+        };
+        s.set_up(physics, false);
+
+        s
+    }
+
+    /// `wish_up` is ignored while overloaded.
+    pub fn update(&mut self, physics: &mut Physics, data: &ShieldData, wish_up: bool, dt: f32) {
+        self.flux = (self.flux - data.flux_regen * dt).max(0.0);
+        if self.overloaded && self.flux <= 0.0 {
+            self.overloaded = false;
+        }
+
+        self.set_up(physics, wish_up && !self.overloaded);
+    }
+
+    fn set_up(&mut self, physics: &mut Physics, up: bool) {
+        if self.up != up {
+            self.up = up;
+            physics.set_shield_enabled(self.collider, up);
+        }
+    }
+
+    /// Returns the damage that the shield could not absorb.
+    /// Nothing is absorbed if the shield is down or
+    /// the local space point is outside the shield's arc.
+    pub fn absorb(&mut self, data: &ShieldData, local_point: Point2<f32>, amount: f32) -> f32 {
+        if !self.up || !data.covers(local_point) {
+            return amount;
+        }
+
+        self.flux += amount;
+        if self.flux > data.flux_max {
+            // The shield will be lowered on next update.
+            let overflow = self.flux - data.flux_max;
+            self.flux = data.flux_max;
+            self.overloaded = true;
+            overflow
+        } else {
+            0.0
+        }
+    }
+
+    /// `[0..1]` relative to flux max.
+    pub fn flux_fraction(&self, data: &ShieldData) -> f32 {
+        if data.flux_max > 0.0 {
+            self.flux / data.flux_max
+        } else {
+            1.0
+        }
+    }
+
+    pub fn save(&self, wish_up: bool) -> ShieldSave {
+        ShieldSave {
+            flux: self.flux,
+            wish_up,
+            overloaded: self.overloaded,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+pub struct ShieldSave {
+    pub flux: f32,
+    pub wish_up: bool,
+    pub overloaded: bool,
+}
+
+// ####################################################################################
+// ################################### DATA ###########################################
+// ####################################################################################
+
+pub struct ShieldData {
+    pub radius: f32,
+    /// Centered on the entity's forward. `PI` or more cover everything.
+    pub half_arc: f32,
+    pub flux_max: f32,
+    /// Flux dissipated per second.
+    pub flux_regen: f32,
+}
+impl ShieldData {
+    /// If the local space point is inside the shield's arc.
+    pub fn covers(&self, local_point: Point2<f32>) -> bool {
+        Self::arc_covers(self.half_arc, local_point)
+    }
+
+    /// Also used by physics which only has the half arc.
+    pub fn arc_covers(half_arc: f32, local_point: Point2<f32>) -> bool {
+        half_arc >= std::f32::consts::PI || local_point.y.atan2(local_point.x).abs() <= half_arc
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ShieldDataJson {
+    radius: f32,
+    /// Full arc in radian.
+    arc: f32,
+    flux_max: f32,
+    flux_regen: f32,
+}
+impl ShieldDataJson {
+    pub fn parse(self) -> ShieldData {
+        ShieldData {
+            radius: self.radius,
+            half_arc: self.arc * 0.5,
+            flux_max: self.flux_max,
+            flux_regen: self.flux_regen,
+        }
+    }
+}
+
+// ####################################################################################
+// ################################### TEST ###########################################
+// ####################################################################################
+
+#[test]
+fn test_shield_absorb() {
+    let data = ShieldData {
+        radius: 1.0,
+        half_arc: std::f32::consts::FRAC_PI_2,
+        flux_max: 10.0,
+        flux_regen: 1.0,
+    };
+    let mut shield = Shield {
+        collider: ColliderHandle::invalid(),
+        flux: 0.0,
+        up: true,
+        overloaded: false,
+    };
+
+    // Behind is not covered.
+    approx::assert_relative_eq!(shield.absorb(&data, point![-1.0, 0.1], 4.0), 4.0);
+
+    approx::assert_relative_eq!(shield.absorb(&data, point![1.0, 0.5], 4.0), 0.0);
+    approx::assert_relative_eq!(shield.flux, 4.0);
+    assert!(!shield.overloaded);
+
+    // Overload.
+    approx::assert_relative_eq!(shield.absorb(&data, point![1.0, -0.5], 8.0), 2.0);
+    approx::assert_relative_eq!(shield.flux_fraction(&data), 1.0);
+    assert!(shield.overloaded);
+}
@@ -0,0 +1,126 @@
+use super::*;
+
+/// Stat modifiers past this are ignored.
+pub const MAX_STAT_MODIFIERS: usize = 16;
+
+/// Entity stat which can be modified. See [StatModifier].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Stat {
+    LinearAcceleration,
+    AngularAcceleration,
+    MaxLinearVelocity,
+    MaxAngularVelocity,
+    ArmorMax,
+    /// Hull per second. Can be negative.
+    HullRegen,
+}
+
+/// What happens when a modifier with the same source and stat is already applied.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum Stacking {
+    /// Both apply.
+    #[default]
+    Stack,
+    /// Replace the existing one, refreshing its duration.
+    Refresh,
+    /// Keep the existing one.
+    Ignore,
+}
+
+/// A buff or debuff. Modified value is `(base + add) * multiplier`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct StatModifier {
+    /// Modifiers from the same source follow stacking rules with each other.
+    pub source: u32,
+    pub stat: Stat,
+    pub add: f32,
+    pub multiplier: f32,
+    /// Seconds left. Permanent when none.
+    pub remaining: Option<f32>,
+    pub stacking: Stacking,
+}
+impl StatModifier {
+    /// Replace non-finite values.
+    pub fn sanitize(mut self) -> Self {
+        if !self.add.is_finite() {
+            self.add = 0.0;
+        }
+        if !self.multiplier.is_finite() {
+            self.multiplier = 1.0;
+        }
+        self.remaining = self.remaining.filter(|remaining| remaining.is_finite());
+        self
+    }
+
+    /// Returns if expired.
+    pub fn tick(&mut self, dt: f32) -> bool {
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= dt;
+            *remaining <= 0.0
+        } else {
+            false
+        }
+    }
+}
+
+/// Modified value of `stat`.
+/// Only hull regen can be negative.
+pub fn modified_stat<'a>(
+    stat: Stat,
+    base: f32,
+    modifiers: impl Iterator<Item = &'a StatModifier>,
+) -> f32 {
+    let mut add = 0.0;
+    let mut multiplier = 1.0;
+    for modifier in modifiers.filter(|modifier| modifier.stat == stat) {
+        add += modifier.add;
+        multiplier *= modifier.multiplier;
+    }
+
+    let value = (base + add) * multiplier;
+    if stat == Stat::HullRegen {
+        value
+    } else {
+        value.max(0.0)
+    }
+}
+
+// ####################################################################################
+// ################################### TEST ###########################################
+// ####################################################################################
+
+#[test]
+fn test_modified_stat() {
+    let modifier = |stat, add, multiplier| StatModifier {
+        source: 0,
+        stat,
+        add,
+        multiplier,
+        remaining: None,
+        stacking: Stacking::Stack,
+    };
+    let modifiers = [
+        modifier(Stat::MaxLinearVelocity, 2.0, 1.0),
+        modifier(Stat::MaxLinearVelocity, 0.0, 0.5),
+        modifier(Stat::ArmorMax, -100.0, 1.0),
+        modifier(Stat::HullRegen, -5.0, 1.0),
+    ];
+
+    approx::assert_relative_eq!(
+        modified_stat(Stat::MaxLinearVelocity, 10.0, modifiers.iter()),
+        6.0
+    );
+    approx::assert_relative_eq!(modified_stat(Stat::ArmorMax, 10.0, modifiers.iter()), 0.0);
+    approx::assert_relative_eq!(modified_stat(Stat::HullRegen, 1.0, modifiers.iter()), -4.0);
+    approx::assert_relative_eq!(
+        modified_stat(Stat::LinearAcceleration, 3.0, modifiers.iter()),
+        3.0
+    );
+
+    let mut timed = StatModifier {
+        remaining: Some(1.0),
+        ..modifier(Stat::ArmorMax, 1.0, 1.0)
+    };
+    assert!(!timed.tick(0.5));
+    assert!(timed.tick(0.5));
+}
@@ -0,0 +1,271 @@
+use super::*;
+use std::f32::consts::PI;
+
+/// How close in radian a turret needs to be from its aim to be on target.
+const ON_TARGET_TOLERANCE: f32 = 0.05;
+
+/// A weapon slot's state.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Turret {
+    pub weapon: Option<WeaponDataId>,
+    /// Radian relative to the slot's rest angle.
+    pub rotation: f32,
+    /// Seconds until the weapon can fire again.
+    pub cooldown: f32,
+    /// If the turret is pointing at its aim.
+    #[serde(skip)]
+    pub on_target: bool,
+}
+impl Turret {
+    pub fn new(slot: &WeaponSlot) -> Self {
+        Self {
+            weapon: slot.builtin_weapon(),
+            ..Default::default()
+        }
+    }
+
+    pub fn ready(&self) -> bool {
+        self.weapon.is_some() && self.cooldown <= 0.0
+    }
+
+    /// Put the weapon on cooldown.
+    /// Returns the fired weapon if it was ready.
+    pub fn fire(&mut self) -> Option<WeaponDataId> {
+        if !self.ready() {
+            return None;
+        }
+
+        let weapon = self.weapon?;
+        self.cooldown = weapon.cooldown;
+        Some(weapon)
+    }
+
+    /// Local space angle.
+    pub fn angle(&self, slot: &WeaponSlot) -> f32 {
+        slot.angle + self.rotation
+    }
+
+    /// `aim` is a local space position.
+    /// Turret return to its rest angle when there is no aim.
+    pub fn update(&mut self, slot: &WeaponSlot, aim: Option<Point2<f32>>, dt: f32) {
+        self.cooldown = (self.cooldown - dt).max(0.0);
+
+        let Some(weapon) = self.weapon else {
+            self.on_target = false;
+            return;
+        };
+
+        let wish_rotation = aim.map_or(0.0, |aim| {
+            let to = aim.coords - slot.translation;
+            wrap_angle(to.y.atan2(to.x) - slot.angle)
+        });
+
+        let offset = if slot.unlimited() {
+            wrap_angle(wish_rotation - self.rotation)
+        } else {
+            wish_rotation.clamp(-slot.half_arc, slot.half_arc) - self.rotation
+        };
+
+        let max_step = weapon.rotation_speed * dt;
+        self.rotation += offset.clamp(-max_step, max_step);
+        if slot.unlimited() {
+            self.rotation = wrap_angle(self.rotation);
+        }
+
+        self.on_target =
+            aim.is_some() && wrap_angle(wish_rotation - self.rotation).abs() < ON_TARGET_TOLERANCE;
+    }
+}
+
+// ####################################################################################
+// ################################### DATA ###########################################
+// ####################################################################################
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Default)]
+pub enum WeaponSize {
+    #[default]
+    Light,
+    Medium,
+    Heavy,
+}
+
+/// Where a weapon can be mounted on an entity.
+pub struct WeaponSlot {
+    /// Local space.
+    pub translation: Vector2<f32>,
+    /// Local space rest angle. 0 is forward.
+    pub angle: f32,
+    /// How far the turret can rotate away from its rest angle.
+    /// `PI` or more can rotate freely.
+    pub half_arc: f32,
+    pub size: WeaponSize,
+    builtin_weapon: Option<u32>,
+}
+impl WeaponSlot {
+    /// Built-in weapon can not be removed.
+    pub fn builtin_weapon(&self) -> Option<WeaponDataId> {
+        self.builtin_weapon
+            .and_then(|id| WeaponDataId::try_from(id).ok())
+    }
+
+    /// Smaller weapons can be mounted in bigger slots.
+    pub fn fits(&self, weapon: &WeaponData) -> bool {
+        weapon.size <= self.size
+    }
+
+    fn unlimited(&self) -> bool {
+        self.half_arc >= PI
+    }
+}
+
+pub struct WeaponData {
+    pub id: u32,
+
+    pub size: WeaponSize,
+    /// Radian per second.
+    pub rotation_speed: f32,
+    /// Seconds between shots.
+    pub cooldown: f32,
+    pub range: f32,
+
+    pub damage: f32,
+    pub projectile_speed: f32,
+}
+impl WeaponData {
+    /// Seconds for a projectile to reach max range.
+    pub fn projectile_lifetime(&self) -> f32 {
+        if self.projectile_speed > 0.0 {
+            self.range / self.projectile_speed
+        } else {
+            0.0
+        }
+    }
+}
+
+// ####################################################################################
+// ############################## DATA JSON ###########################################
+// ####################################################################################
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct WeaponSlotJson {
+    translation: Vector2<f32>,
+    angle: f32,
+    /// Full arc in radian.
+    arc: f32,
+    size: WeaponSize,
+    builtin_weapon: Option<u32>,
+}
+impl WeaponSlotJson {
+    pub fn parse(self, weapons: &[WeaponData]) -> anyhow::Result<WeaponSlot> {
+        let slot = WeaponSlot {
+            translation: self.translation,
+            angle: self.angle,
+            half_arc: self.arc * 0.5,
+            size: self.size,
+            builtin_weapon: self.builtin_weapon,
+        };
+
+        if let Some(id) = slot.builtin_weapon {
+            let weapon = weapons
+                .get(id as usize)
+                .with_context(|| format!("Built-in weapon {} out of bound", id))?;
+            anyhow::ensure!(
+                slot.fits(weapon),
+                "Built-in weapon {} does not fit its slot",
+                id
+            );
+        }
+
+        Ok(slot)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct WeaponDataJson {
+    size: WeaponSize,
+    rotation_speed: f32,
+    cooldown: f32,
+    range: f32,
+    damage: f32,
+    projectile_speed: f32,
+}
+impl WeaponDataJson {
+    pub fn parse(self, id: u32) -> WeaponData {
+        WeaponData {
+            id,
+            size: self.size,
+            rotation_speed: self.rotation_speed,
+            cooldown: self.cooldown,
+            range: self.range,
+            damage: self.damage,
+            projectile_speed: self.projectile_speed,
+        }
+    }
+}
+
+// ####################################################################################
+// ################################### TEST ###########################################
+// ####################################################################################
+
+#[test]
+fn test_turret_rotation() {
+    use std::f32::consts::FRAC_PI_2;
+
+    let mut slot = WeaponSlot {
+        translation: Vector2::new(1.0, 0.0),
+        angle: 0.0,
+        half_arc: FRAC_PI_2,
+        size: WeaponSize::Medium,
+        builtin_weapon: None,
+    };
+    let weapon = WeaponDataId(Box::leak(Box::new(WeaponData {
+        id: 0,
+        size: WeaponSize::Light,
+        rotation_speed: 2.0,
+        cooldown: 1.0,
+        range: 10.0,
+        damage: 1.0,
+        projectile_speed: 10.0,
+    })));
+    let mut turret = Turret {
+        weapon: Some(weapon),
+        ..Default::default()
+    };
+
+    // Aim behind is clamped to the arc.
+    for _ in 0..1000 {
+        turret.update(&slot, Some(point![0.0, 0.1]), DT);
+    }
+    approx::assert_relative_eq!(turret.rotation, FRAC_PI_2, epsilon = 0.001);
+    assert!(!turret.on_target);
+
+    // Rotation speed is respected.
+    turret.rotation = 0.0;
+    turret.update(&slot, Some(point![1.0, 1.0]), DT);
+    assert!(turret.rotation <= weapon.rotation_speed * DT + 0.0001);
+
+    // Unlimited turret takes the shortest path.
+    slot.half_arc = PI;
+    turret.rotation = 3.0;
+    for _ in 0..1000 {
+        turret.update(&slot, Some(point![1.0, -0.1]), DT);
+    }
+    approx::assert_relative_eq!(turret.rotation, -FRAC_PI_2, epsilon = 0.001);
+    assert!(turret.on_target);
+
+    // Return to rest.
+    for _ in 0..1000 {
+        turret.update(&slot, None, DT);
+    }
+    approx::assert_relative_eq!(turret.rotation, 0.0, epsilon = 0.001);
+}
+
+#[test]
+fn test_weapon_slot_parse_builtin_out_of_bound() {
+    let slot = WeaponSlotJson {
+        builtin_weapon: Some(3),
+        ..Default::default()
+    };
+    assert!(slot.parse(&[]).is_err());
+}
@@ -1,13 +1,16 @@
 use super::*;
 
+/// Offset under which AimSmooth start slowing down.
+const AIM_SMOOTH_CLOSE: f32 = 0.2;
+
 pub fn integrate_linear_velocity(
-    linear_velocity: Vec2,
-    wish_linear_velocity: Vec2,
+    linear_velocity: Vector2<f32>,
+    wish_linear_velocity: Vector2<f32>,
     linear_acceleration: f32,
     delta: f32,
-) -> Vec2 {
-    return linear_velocity
-        + (wish_linear_velocity - linear_velocity).clamp_length_max(linear_acceleration * delta);
+) -> Vector2<f32> {
+    linear_velocity
+        + (wish_linear_velocity - linear_velocity).cap_magnitude(linear_acceleration * delta)
 }
 
 pub fn integrate_angular_velocity(
@@ -16,39 +19,142 @@ pub fn integrate_angular_velocity(
     angular_acceleration: f32,
     delta: f32,
 ) -> f32 {
-    return angular_velocity
+    angular_velocity
         + f32::clamp(
             wish_angular_velocity - angular_velocity,
             -angular_acceleration * delta,
             angular_acceleration * delta,
-        );
+        )
+}
+
+/// Result is in the range `[-PI, PI]`.
+pub fn wrap_angle(angle: f32) -> f32 {
+    na::UnitComplex::new(angle).angle()
 }
 
 /// Return an angle such that `angle + this` point toward `to`.
 /// Result is in the range `[-PI, PI]`.
-pub fn angle_to(angle: f32, to: Vec2) -> f32 {
-    return Vec2::from_angle(angle).angle_between(to);
+pub fn angle_to(angle: f32, to: Vector2<f32>) -> f32 {
+    wrap_angle(to.y.atan2(to.x) - angle)
+}
+
+/// Return the wish angular velocity to face `to` without overshooting.
+/// Start braking once we could not stop before reaching the target.
+pub fn aim_smooth(
+    rotation: f32,
+    angular_velocity: f32,
+    to: Vector2<f32>,
+    max_angular_velocity: f32,
+    angular_acceleration: f32,
+) -> f32 {
+    let offset = angle_to(rotation, to);
+    let wish_dir = offset.signum();
+
+    let mut close_smooth = offset.abs().min(AIM_SMOOTH_CLOSE) / AIM_SMOOTH_CLOSE;
+
+    if wish_dir == angular_velocity.signum() {
+        let time_to_target = (offset / angular_velocity).abs();
+        let time_to_stop = (angular_velocity / angular_acceleration).abs();
+        if time_to_target < time_to_stop {
+            close_smooth *= -1.0;
+        }
+    }
+
+    wish_dir * max_angular_velocity * close_smooth
 }
 
 #[test]
 fn test_angle_to() {
+    use rand::rngs::StdRng;
+    use std::f32::consts::PI;
+
     let epsilon = 0.001;
 
-    approx::assert_relative_eq!(angle_to(0.0, vec2(1.0, 0.0)), 0.0, epsilon = epsilon);
+    approx::assert_relative_eq!(angle_to(0.0, vector![1.0, 0.0]), 0.0, epsilon = epsilon);
     approx::assert_relative_eq!(
-        angle_to(0.0, vec2(0.0, -1.0)),
-        -FRAC_PI_2,
+        angle_to(0.0, vector![0.0, -1.0]),
+        -PI * 0.5,
+        epsilon = epsilon
+    );
+    approx::assert_relative_eq!(
+        angle_to(0.0, vector![0.0, 1.0]),
+        PI * 0.5,
         epsilon = epsilon
     );
-    approx::assert_relative_eq!(angle_to(0.0, vec2(0.0, 1.0)), FRAC_PI_2, epsilon = epsilon);
 
-    let mut rng = rand::thread_rng();
+    let mut rng = StdRng::seed_from_u64(0);
     for _ in 0..100 {
         let a = rng.gen_range(-PI..PI);
         let b = a + rng.gen_range(-PI..PI);
         let expected = b - a;
-        let v = Vec2::from_angle(b);
+        let v = vector![b.cos(), b.sin()];
 
         approx::assert_relative_eq!(angle_to(a, v), expected, epsilon = epsilon);
     }
 }
+
+#[test]
+fn test_aim_smooth() {
+    const MAX_ANGULAR_VELOCITY: f32 = 2.0;
+    const ANGULAR_ACCELERATION: f32 = 4.0;
+
+    /// Returns the final offset and the largest overshoot.
+    fn simulate(mut rotation: f32, mut angular_velocity: f32, target: f32) -> (f32, f32) {
+        let to = vector![target.cos(), target.sin()];
+        let start_dir = angle_to(rotation, to).signum();
+
+        let mut overshoot = 0.0f32;
+        for _ in 0..200 {
+            let wish = aim_smooth(
+                rotation,
+                angular_velocity,
+                to,
+                MAX_ANGULAR_VELOCITY,
+                ANGULAR_ACCELERATION,
+            );
+            angular_velocity =
+                integrate_angular_velocity(angular_velocity, wish, ANGULAR_ACCELERATION, DT);
+            rotation += angular_velocity * DT;
+
+            let offset = angle_to(rotation, to);
+            if offset.signum() != start_dir {
+                overshoot = overshoot.max(offset.abs());
+            }
+        }
+
+        (angle_to(rotation, to), overshoot)
+    }
+
+    for (rotation, angular_velocity, target) in [
+        // From rest.
+        (0.0, 0.0, 1.0),
+        (0.0, 0.0, -1.0),
+        (0.0, 0.0, 3.0),
+        (1.0, 0.0, -2.5),
+        // Already turning toward target at max speed.
+        (0.0, MAX_ANGULAR_VELOCITY, 0.5),
+        (0.0, -MAX_ANGULAR_VELOCITY, -0.5),
+        // Turning away from target.
+        (0.0, -MAX_ANGULAR_VELOCITY, 1.5),
+        // Crossing PI.
+        (3.0, 0.0, -3.0),
+    ] {
+        let (offset, overshoot) = simulate(rotation, angular_velocity, target);
+        assert!(
+            offset.abs() < 0.001,
+            "did not reach target: {} {} {} -> {}",
+            rotation,
+            angular_velocity,
+            target,
+            offset
+        );
+        assert!(
+            overshoot < 0.02,
+            "overshot: {} {} {} -> {}",
+            rotation,
+            angular_velocity,
+            target,
+            overshoot
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use super::*;
+
+/// Hull of a new wreck relative to its data's hull max.
+pub const WRECK_HULL: f32 = 0.5;
+/// Seconds for a wreck to lose its data's hull max.
+const WRECK_DECAY_TIME: f32 = 60.0 * 60.0;
+/// The most damaged wreck is removed to make room past this.
+const MAX_WRECKS: usize = 128;
+
+/// Distance between hulls to salvage.
+const SALVAGE_RANGE: f32 = 3.0;
+/// Hull taken from a wreck per second.
+const SALVAGE_RATE: f32 = 10.0;
+
+/// Hull lost per second.
+pub fn wreck_decay_rate(data: &EntityData) -> f32 {
+    data.hull_max / WRECK_DECAY_TIME
+}
+
+/// Distance between centers to salvage. Ai keep a bit closer.
+pub fn salvage_distance(salvager: &EntityData, wreck: &EntityData) -> f32 {
+    radius(salvager) + radius(wreck) + SALVAGE_RANGE
+}
+
+/// Bounding radius around the body's center.
+fn radius(data: &EntityData) -> f32 {
+    let sphere = data.shape.compute_local_bounding_sphere();
+    sphere.radius + (sphere.center.coords + data.shape_translation).magnitude()
+}
+
+/// Take hull from a wreck in range and repair the salvager with it.
+pub fn salvage(sim: &mut Simulation, entity_idx: usize, wreck_id: EntityId) {
+    let Some(wreck_idx) = sim
+        .entities
+        .get_index_of(&wreck_id)
+        .filter(|&wreck_idx| wreck_idx != entity_idx && sim.entities[wreck_idx].wreck)
+    else {
+        sim.entities[entity_idx].wish_salvage = None;
+        return;
+    };
+
+    let entity = &sim.entities[entity_idx];
+    let wreck = &sim.entities[wreck_idx];
+    let distance = (sim.physics.body(entity.rb).translation()
+        - sim.physics.body(wreck.rb).translation())
+    .magnitude();
+    if distance > salvage_distance(&entity.data, &wreck.data) {
+        return;
+    }
+
+    let salvaged = sim.entities[wreck_idx].take_hull(SALVAGE_RATE * sim.sim_dt);
+    sim.entities[entity_idx].repair(salvaged);
+}
+
+impl Simulation {
+    /// Remains of a destroyed entity. Keeps its velocity.
+    pub fn spawn_wreck(
+        &mut self,
+        data: EntityDataId,
+        position: Isometry2<f32>,
+        linvel: Vector2<f32>,
+        angvel: f32,
+    ) {
+        let num_wrecks = self.entities.values().filter(|entity| entity.wreck).count();
+        if num_wrecks >= MAX_WRECKS {
+            let most_damaged = self
+                .entities
+                .iter()
+                .filter(|(_, entity)| entity.wreck)
+                .min_by(|(_, a), (_, b)| a.hull_ratio().total_cmp(&b.hull_ratio()))
+                .map(|(&entity_id, _)| entity_id);
+            if let Some(entity_id) = most_damaged {
+                self.despawn_entity(entity_id, None);
+            }
+        }
+
+        self.spawn_entity(
+            EntitySave::new_wreck(data, position, linvel, angvel),
+            None,
+            None,
+            None,
+        );
+    }
+
+    /// Spawn saved wrecks.
+    /// They also decayed while the simulation was not running.
+    pub fn load_wrecks(&mut self, save: SimulationSave) {
+        let offline = (self.global_time - save.saved_at).max(0.0) as f32;
+
+        for mut wreck in save.wrecks {
+            if wreck.decay_wreck(offline) {
+                wreck.wreck = true;
+                self.spawn_entity(wreck, None, None, None);
+            }
+        }
+    }
+}
+
+// ####################################################################################
+// ################################### TEST ###########################################
+// ####################################################################################
+
+#[test]
+fn test_salvage_distance() {
+    let data = EntityDataJson::default().parse(0, &[]).unwrap();
+    // Default shape is a ball of radius 0.5.
+    approx::assert_relative_eq!(salvage_distance(&data, &data), 1.0 + SALVAGE_RANGE);
+}
+
+#[cfg(test)]
+fn test_wreck_ids(sim: &Simulation) -> Vec<EntityId> {
+    sim.entities
+        .iter()
+        .filter(|(_, entity)| entity.wreck)
+        .map(|(&entity_id, _)| entity_id)
+        .collect()
+}
+
+#[test]
+fn test_wreck_decay() {
+    let (mut sim, _) = test_simulation();
+    let data = entity::test_entity_data(serde_json::json!({ "hull": 100.0 }));
+
+    // Offline decay.
+    let wreck = EntitySave::new_wreck(data, Isometry2::identity(), Vector2::zeros(), 0.0);
+    sim.load_wrecks(SimulationSave {
+        wrecks: vec![wreck.clone(), wreck],
+        saved_at: sim.global_time - (WRECK_DECAY_TIME * 0.25) as f64,
+    });
+    let wreck_ids = test_wreck_ids(&sim);
+    assert_eq!(wreck_ids.len(), 2);
+    let hull_ratio = sim.entities[&wreck_ids[0]].hull_ratio();
+    approx::assert_relative_eq!(hull_ratio, 0.25, epsilon = 0.01);
+
+    // Live decay.
+    sim.step();
+    assert!(sim.entities[&wreck_ids[0]].hull_ratio() < hull_ratio);
+
+    // Fully decayed while offline.
+    let wreck = EntitySave::new_wreck(data, Isometry2::identity(), Vector2::zeros(), 0.0);
+    let (mut sim, _) = test_simulation();
+    sim.load_wrecks(SimulationSave {
+        wrecks: vec![wreck],
+        saved_at: sim.global_time - WRECK_DECAY_TIME as f64,
+    });
+    assert!(sim.entities.is_empty());
+}
+
+#[test]
+fn test_salvage() {
+    let (mut sim, _) = test_simulation();
+    let data = entity::test_entity_data(serde_json::json!({ "hull": 100.0 }));
+    let (_, entity_idx) = sim.spawn_entity(
+        EntitySave::new(data, None, Isometry2::identity(), Vector2::zeros(), 0.0),
+        None,
+        None,
+        None,
+    );
+    sim.entities[entity_idx].take_hull(80.0);
+    sim.spawn_wreck(
+        data,
+        Isometry2::translation(2.0, 0.0),
+        Vector2::zeros(),
+        0.0,
+    );
+    let wreck_id = test_wreck_ids(&sim)[0];
+
+    salvage(&mut sim, entity_idx, wreck_id);
+    let salvaged = SALVAGE_RATE * sim.sim_dt / 100.0;
+    approx::assert_relative_eq!(sim.entities[entity_idx].hull_ratio(), 0.2 + salvaged);
+    approx::assert_relative_eq!(sim.entities[&wreck_id].hull_ratio(), WRECK_HULL - salvaged);
+
+    // Out of range.
+    let rb = sim.entities[&wreck_id].rb;
+    sim.physics
+        .body_mut(rb)
+        .set_translation(vector![salvage_distance(&data, &data) + 1.0, 0.0], true);
+    salvage(&mut sim, entity_idx, wreck_id);
+    approx::assert_relative_eq!(sim.entities[entity_idx].hull_ratio(), 0.2 + salvaged);
+
+    // Only wrecks can be salvaged.
+    let entity_id = *sim.entities.get_index(entity_idx).unwrap().0;
+    sim.entities[entity_idx].wish_salvage = Some(entity_id);
+    salvage(&mut sim, entity_idx, entity_id);
+    assert_eq!(sim.entities[entity_idx].wish_salvage, None);
+}
+
+#[test]
+fn test_max_wrecks() {
+    let (mut sim, _) = test_simulation();
+    let data = entity::test_entity_data(serde_json::json!({ "hull": 100.0 }));
+    for i in 0..MAX_WRECKS {
+        let position = Isometry2::translation(i as f32 * 2.0, 0.0);
+        sim.spawn_wreck(data, position, Vector2::zeros(), 0.0);
+    }
+    let most_damaged = test_wreck_ids(&sim)[7];
+    sim.entities[&most_damaged].take_hull(10.0);
+
+    sim.spawn_wreck(
+        data,
+        Isometry2::translation(-2.0, 0.0),
+        Vector2::zeros(),
+        0.0,
+    );
+    let wreck_ids = test_wreck_ids(&sim);
+    assert_eq!(wreck_ids.len(), MAX_WRECKS);
+    assert!(!wreck_ids.contains(&most_damaged));
+}
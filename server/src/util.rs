@@ -1 +1,94 @@
 use super::*;
+
+/// Serde `with` module for `Vec<T>`.
+///
+/// An element which fails to deserialize (e.g. removed enum variant) is dropped
+/// instead of failing the whole vec. Json keeps plain elements and skips them as values.
+/// Binary formats aren't self-describing so each element is encoded on its own.
+pub mod tolerant_vec {
+    use super::*;
+    use serde::{de::DeserializeOwned, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, T: Serialize>(
+        vec: &[T],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_seq(vec)
+        } else {
+            serializer.collect_seq(vec.iter().map(bin_encode))
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: DeserializeOwned>(
+        deserializer: D,
+    ) -> Result<Vec<T>, D::Error> {
+        let results: Vec<anyhow::Result<T>> = if deserializer.is_human_readable() {
+            Vec::<serde_json::Value>::deserialize(deserializer)?
+                .into_iter()
+                .map(|value| Ok(serde_json::from_value(value)?))
+                .collect()
+        } else {
+            Vec::<Vec<u8>>::deserialize(deserializer)?
+                .iter()
+                .map(|buf| bin_decode(buf))
+                .collect()
+        };
+
+        Ok(results
+            .into_iter()
+            .filter_map(|result| match result {
+                Ok(v) => Some(v),
+                Err(err) => {
+                    log::warn!("Dropped element which failed to deserialize: {}", err);
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
+// ####################################################################################
+// ############## TEST ################################################################
+// ####################################################################################
+
+#[test]
+fn test_tolerant_vec() {
+    #[derive(Serialize)]
+    enum Old {
+        Kept(u32),
+        Removed { a: f32, b: u8 },
+    }
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum New {
+        Kept(u32),
+    }
+
+    #[derive(Serialize)]
+    struct OldSave {
+        #[serde(with = "tolerant_vec")]
+        v: Vec<Old>,
+        after: u8,
+    }
+    #[derive(Deserialize)]
+    struct NewSave {
+        #[serde(with = "tolerant_vec")]
+        v: Vec<New>,
+        after: u8,
+    }
+
+    let old = OldSave {
+        v: vec![Old::Kept(1), Old::Removed { a: 1.0, b: 2 }, Old::Kept(3)],
+        after: 7,
+    };
+
+    let save: NewSave = bin_decode(&bin_encode(&old)).unwrap();
+    assert_eq!(save.v, vec![New::Kept(1), New::Kept(3)]);
+    assert_eq!(save.after, 7);
+
+    let json = serde_json::to_string(&old).unwrap();
+    assert!(json.contains("Removed"));
+    let save: NewSave = serde_json::from_str(&json).unwrap();
+    assert_eq!(save.v, vec![New::Kept(1), New::Kept(3)]);
+    assert_eq!(save.after, 7);
+}